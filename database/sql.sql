@@ -1,3 +1,7 @@
+DROP table IF EXISTS `upload_jobs`;
+DROP table IF EXISTS `upload_parts`;
+DROP table IF EXISTS `cast_text`;
+DROP table IF EXISTS `commands`;
 DROP table IF EXISTS `marks`;
 DROP table IF EXISTS `casts`;
 DROP table IF EXISTS `heartbeats`;
@@ -9,8 +13,12 @@ CREATE TABLE logs (
   note        TEXT            NOT NULL DEFAULT '',
   uploaded_at TIMESTAMP(0)    NOT NULL DEFAULT CURRENT_TIMESTAMP(0),
   visible     BOOLEAN         NOT NULL DEFAULT TRUE,
+  content_hash    CHAR(64)     NULL DEFAULT NULL,
+  idempotency_key VARCHAR(255) NULL DEFAULT NULL,
   PRIMARY KEY (id),
-  UNIQUE KEY uk_logs_uuid (uuid)
+  UNIQUE KEY uk_logs_uuid (uuid),
+  UNIQUE KEY uk_logs_content (content_hash),
+  UNIQUE KEY uk_logs_idempotency (idempotency_key)
 ) ENGINE=InnoDB;
 
 CREATE TABLE casts (
@@ -18,10 +26,18 @@ CREATE TABLE casts (
   uuid            UUID            NOT NULL,
   bucket          TEXT            NOT NULL,
   path            TEXT            NOT NULL,
+  original_path   TEXT            NULL DEFAULT NULL,
   size_byte       BIGINT UNSIGNED NOT NULL,
+  width           SMALLINT UNSIGNED NOT NULL DEFAULT 80,
+  height          SMALLINT UNSIGNED NOT NULL DEFAULT 24,
   duration        BIGINT UNSIGNED NOT NULL,
   active_duration BIGINT UNSIGNED NOT NULL,
+  activity        MEDIUMTEXT      NULL DEFAULT NULL,
   event_count     INT UNSIGNED    NOT NULL,
+  repaired_events INT UNSIGNED    NOT NULL DEFAULT 0,
+  truncated_at    BIGINT UNSIGNED NULL DEFAULT NULL,
+  truncated_reason TEXT           NULL DEFAULT NULL,
+  sha256          CHAR(64)        NULL DEFAULT NULL,
   started_at      TIMESTAMP(0)    NOT NULL,
   PRIMARY KEY (id),
   KEY idx_casts_uuid (uuid),
@@ -57,3 +73,66 @@ CREATE TABLE marks (
     REFERENCES casts(id)
     ON DELETE CASCADE
 ) ENGINE=InnoDB;
+
+CREATE TABLE commands (
+  id          BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  cast_id     BIGINT UNSIGNED NOT NULL,
+  second      DOUBLE          NOT NULL,
+  line        TEXT            NOT NULL,
+  approximate BOOLEAN         NOT NULL DEFAULT FALSE,
+  exit_status INT             NULL,
+  cwd         TEXT            NULL,
+  duration    DOUBLE          NULL,
+  PRIMARY KEY (id),
+  KEY idx_commands_cast (cast_id, second),
+  FULLTEXT KEY ft_commands_line (line),
+  CONSTRAINT fk_commands_cast
+    FOREIGN KEY (cast_id)
+    REFERENCES casts(id)
+    ON DELETE CASCADE
+) ENGINE=InnoDB;
+
+CREATE TABLE cast_text (
+  id          BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  cast_id     BIGINT UNSIGNED NOT NULL,
+  second      DOUBLE          NOT NULL,
+  body        TEXT            NOT NULL,
+  PRIMARY KEY (id),
+  KEY idx_cast_text_cast (cast_id, second),
+  FULLTEXT KEY ft_cast_text_body (body),
+  CONSTRAINT fk_cast_text_cast
+    FOREIGN KEY (cast_id)
+    REFERENCES casts(id)
+    ON DELETE CASCADE
+) ENGINE=InnoDB;
+
+CREATE TABLE upload_parts (
+  upload_id   VARCHAR(64)     NOT NULL,
+  part        INT UNSIGNED    NOT NULL,
+  total       INT UNSIGNED    NOT NULL,
+  notes       TEXT            NOT NULL DEFAULT '',
+  payload     LONGTEXT        NOT NULL,
+  received_at TIMESTAMP(0)    NOT NULL DEFAULT CURRENT_TIMESTAMP(0),
+  PRIMARY KEY (upload_id, part)
+) ENGINE=InnoDB;
+
+CREATE TABLE upload_jobs (
+  id             UUID            NOT NULL,
+  log_uuid       UUID            NOT NULL,
+  state          VARCHAR(16)     NOT NULL DEFAULT 'queued',
+  notes          TEXT            NOT NULL DEFAULT '',
+  strict         BOOLEAN         NOT NULL DEFAULT FALSE,
+  progress_done  INT UNSIGNED    NOT NULL DEFAULT 0,
+  progress_total INT UNSIGNED    NOT NULL DEFAULT 0,
+  result         LONGTEXT        NULL DEFAULT NULL,
+  error          TEXT            NULL DEFAULT NULL,
+  idempotency_key VARCHAR(255)   NULL DEFAULT NULL,
+  locked_by      UUID            NULL DEFAULT NULL,
+  locked_at      TIMESTAMP(0)    NULL DEFAULT NULL,
+  created_at     TIMESTAMP(0)    NOT NULL DEFAULT CURRENT_TIMESTAMP(0),
+  updated_at     TIMESTAMP(0)    NOT NULL DEFAULT CURRENT_TIMESTAMP(0) ON UPDATE CURRENT_TIMESTAMP(0),
+  PRIMARY KEY (id),
+  UNIQUE KEY uk_jobs_idempotency (idempotency_key),
+  KEY idx_jobs_state (state, created_at),
+  KEY idx_jobs_lease (state, locked_at)
+) ENGINE=InnoDB;
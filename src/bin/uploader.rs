@@ -0,0 +1,346 @@
+//! Uploads a workspace-logs directory, replacing the clipboard one-liner on the index page.
+//!
+//! ```text
+//! uploader [--dir DIR] [--server URL] [--notes TEXT] [--uuid UUID] [--clipboard] [--part-size BYTES]
+//!          [--encoding zstd|gzip|xz|none]
+//! ```
+//!
+//! Without `--clipboard` the events are streamed to `{server}/api/upload/stream`, casts split into
+//! chunks the server converts as they arrive; the server defaults to `PTY_REPLAY_SERVER`. With
+//! `--clipboard` the payload is written as an OSC 52 sequence instead, ready to be pasted into the
+//! index page. `--part-size` splits the payload into numbered parts that the server reassembles,
+//! for clipboards that cannot hold the whole upload. `--encoding` picks the payload compression
+//! (zstd by default).
+
+use anyhow::{Context, bail};
+use base64::Engine as _;
+use serde::Deserialize;
+use serde_json::json;
+use std::io::Write;
+use std::path::PathBuf;
+
+use pty_replay_web::log;
+
+const DEFAULT_DIR: &str = "/home/student/.local/state/workspace-logs/";
+
+/// Uncompressed bytes per cast line when streaming, so the server never holds a whole cast.
+const STREAM_CHUNK: usize = 4 * 1024 * 1024;
+
+/// How often a 503 from a busy server is retried before giving up.
+const BUSY_RETRIES: usize = 12;
+
+struct Args {
+    dir: PathBuf,
+    server: Option<String>,
+    notes: String,
+    uuid: Option<String>,
+    clipboard: bool,
+    part_size: Option<usize>,
+    encoding: log::Encoding,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        dir: PathBuf::from(DEFAULT_DIR),
+        server: std::env::var("PTY_REPLAY_SERVER").ok(),
+        notes: String::new(),
+        uuid: None,
+        clipboard: false,
+        part_size: None,
+        encoding: log::Encoding::default(),
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().with_context(|| format!("{arg} expects a value"));
+        match arg.as_str() {
+            "--dir" => args.dir = PathBuf::from(value()?),
+            "--server" => args.server = Some(value()?),
+            "--notes" => args.notes = value()?,
+            "--uuid" => args.uuid = Some(value()?),
+            "--clipboard" => args.clipboard = true,
+            "--part-size" => args.part_size = Some(value()?.parse().context("--part-size expects bytes")?),
+            "--encoding" => args.encoding = value()?.parse()?,
+            "-h" | "--help" => {
+                println!(
+                    "uploader [--dir DIR] [--server URL] [--notes TEXT] [--uuid UUID] [--clipboard] [--part-size BYTES] \
+                     [--encoding zstd|gzip|xz|none]"
+                );
+                std::process::exit(0);
+            }
+            _ => bail!("unknown argument {arg}"),
+        }
+    }
+    Ok(args)
+}
+
+#[derive(Default)]
+struct Summary {
+    heartbeats: usize,
+    casts: usize,
+    raw_bytes: usize,
+    encoded_bytes: usize,
+}
+
+/// Encodes `heartbeat.log` and every `*.cast` in `dir` into event lines, oldest cast first. With
+/// `chunk_size` set, casts are split into offset-tagged lines of at most that many uncompressed bytes.
+fn collect(
+    dir: &PathBuf,
+    chunk_size: Option<usize>,
+    encoding: log::Encoding,
+) -> anyhow::Result<(Vec<String>, Summary)> {
+    let mut summary = Summary::default();
+    let mut lines = Vec::new();
+
+    let heartbeat = dir.join("heartbeat.log");
+    if heartbeat.is_file() {
+        let raw = std::fs::read(&heartbeat).with_context(|| format!("read {}", heartbeat.display()))?;
+        let line = log::encode_heartbeat(&raw, encoding)?;
+        eprintln!("heartbeat.log: {} bytes -> {} bytes", raw.len(), line.len());
+        summary.heartbeats = raw.len() / 4;
+        summary.raw_bytes += raw.len();
+        summary.encoded_bytes += line.len();
+        lines.push(line);
+    }
+
+    let mut casts = std::fs::read_dir(dir)
+        .with_context(|| format!("read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cast") && path.is_file())
+        .collect::<Vec<_>>();
+    casts.sort();
+
+    for (idx, path) in casts.iter().enumerate() {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let filename = log::parse_cast_filename(&name)?;
+        let raw = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        let cast_lines = match chunk_size {
+            Some(chunk_size) => log::encode_cast_chunks(filename, &raw, chunk_size, encoding)?,
+            None => vec![log::encode_cast(filename, &raw, encoding)?],
+        };
+        let encoded = cast_lines.iter().map(String::len).sum::<usize>();
+        eprintln!("[{}/{}] {name}: {} bytes -> {encoded} bytes", idx + 1, casts.len(), raw.len());
+        summary.casts += 1;
+        summary.raw_bytes += raw.len();
+        summary.encoded_bytes += encoded;
+        lines.extend(cast_lines);
+    }
+
+    Ok((lines, summary))
+}
+
+/// Groups lines into payloads of at most `part_size` bytes; a single longer line gets a part of its own.
+/// Each payload is prefixed with its `part` line when there is more than one.
+fn split_parts(lines: Vec<String>, part_size: Option<usize>) -> anyhow::Result<Vec<String>> {
+    let Some(part_size) = part_size else {
+        return Ok(vec![lines.join("\n")]);
+    };
+    let mut groups = Vec::<Vec<String>>::new();
+    let mut size = 0;
+    for line in lines {
+        match groups.last_mut() {
+            Some(group) if size + line.len() < part_size => {
+                size += line.len() + 1;
+                group.push(line);
+            }
+            _ => {
+                size = line.len() + 1;
+                groups.push(vec![line]);
+            }
+        }
+    }
+    if groups.len() < 2 {
+        return Ok(groups.into_iter().map(|g| g.join("\n")).collect());
+    }
+
+    let upload_id = uuid::Uuid::new_v4().to_string();
+    let total = groups.len() as u32;
+    groups
+        .iter()
+        .enumerate()
+        .map(|(idx, group)| log::encode_part(&upload_id, idx as u32 + 1, total, group))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct UploadResp {
+    url: String,
+    appended: bool,
+    skipped: Vec<String>,
+    diagnostics: Vec<log::Diagnostic>,
+    #[serde(default)]
+    duplicate: bool,
+}
+
+/// Answer to a paste: either the parts staged so far or the job that will process the upload.
+#[derive(Deserialize)]
+struct Accepted {
+    status_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JobProgress {
+    done: u32,
+    total: u32,
+}
+
+#[derive(Deserialize)]
+struct JobStatus {
+    state: String,
+    progress: JobProgress,
+    error: Option<String>,
+    result: Option<UploadResp>,
+}
+
+fn client() -> anyhow::Result<reqwest::blocking::Client> {
+    Ok(reqwest::blocking::Client::builder().timeout(None).build()?)
+}
+
+/// Sends `req`, waiting and retrying while the server is too busy to convert it.
+fn send(req: reqwest::blocking::RequestBuilder, url: &str) -> anyhow::Result<reqwest::blocking::Response> {
+    for _ in 0..BUSY_RETRIES {
+        let resp = req
+            .try_clone()
+            .context("request cannot be retried")?
+            .send()
+            .with_context(|| format!("POST {url}"))?;
+        if resp.status() != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(resp);
+        }
+        let wait = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok()?.parse().ok())
+            .unwrap_or(5);
+        eprintln!("server busy, retrying in {wait}s");
+        std::thread::sleep(std::time::Duration::from_secs(wait));
+    }
+    req.send().with_context(|| format!("POST {url}"))
+}
+
+/// Posts the whole log to the streaming endpoint.
+fn post_stream(server: &str, args: &Args, logs: String) -> anyhow::Result<UploadResp> {
+    let url = format!("{}/api/upload/stream", server.trim_end_matches('/'));
+    let mut query = vec![("notes", args.notes.clone())];
+    if let Some(uuid) = &args.uuid {
+        query.push(("uuid", uuid.clone()));
+    }
+
+    eprintln!("uploading to {url}");
+    let key = uuid::Uuid::new_v4().to_string();
+    let req = client()?.post(&url).header("Idempotency-Key", key).query(&query).body(logs);
+    let resp = send(req, &url)?;
+    let status = resp.status();
+    if !status.is_success() {
+        bail!("upload failed with {status}: {}", resp.text().unwrap_or_default());
+    }
+    resp.json().context("invalid upload response")
+}
+
+/// Polls an upload job until it is done, reporting conversion progress as it goes.
+fn wait_job(server: &str, status_url: &str) -> anyhow::Result<UploadResp> {
+    let url = format!("{}{status_url}", server.trim_end_matches('/'));
+    let client = client()?;
+    let mut reported = None;
+    loop {
+        let resp = client.get(&url).send().with_context(|| format!("GET {url}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            bail!("job status failed with {status}: {}", resp.text().unwrap_or_default());
+        }
+        let job = resp.json::<JobStatus>().context("invalid job status")?;
+        match job.state.as_str() {
+            "done" => return job.result.context("finished job has no result"),
+            "failed" => bail!("upload failed: {}", job.error.unwrap_or_default()),
+            state => {
+                let now = (state.to_string(), job.progress.done, job.progress.total);
+                if reported.as_ref() != Some(&now) {
+                    eprintln!("{state}: {}/{} cast(s) converted", now.1, now.2);
+                    reported = Some(now);
+                }
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Posts one payload; returns `None` while the server is still waiting for further parts.
+fn post(server: &str, args: &Args, logs: String) -> anyhow::Result<Option<UploadResp>> {
+    let url = format!("{}/api/upload", server.trim_end_matches('/'));
+    let mut body = json!({ "notes": args.notes, "logs": logs });
+    if let Some(uuid) = &args.uuid {
+        body["uuid"] = json!(uuid);
+    }
+
+    eprintln!("uploading to {url}");
+    let key = uuid::Uuid::new_v4().to_string();
+    let resp = send(client()?.post(&url).header("Idempotency-Key", key).json(&body), &url)?;
+    let status = resp.status();
+    if !status.is_success() {
+        bail!("upload failed with {status}: {}", resp.text().unwrap_or_default());
+    }
+    match resp.json::<Accepted>().context("invalid upload response")?.status_url {
+        Some(status_url) => wait_job(server, &status_url).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    if !args.clipboard && args.server.is_none() {
+        bail!("--server or PTY_REPLAY_SERVER is required unless --clipboard is given");
+    }
+    let streaming = !args.clipboard && args.part_size.is_none();
+    let chunk_size = if streaming { Some(STREAM_CHUNK) } else { args.part_size };
+    let (lines, summary) = collect(&args.dir, chunk_size, args.encoding)?;
+    if lines.is_empty() {
+        bail!("nothing to upload in {}", args.dir.display());
+    }
+    let parts = split_parts(lines, args.part_size)?;
+    let parts_len = parts.len();
+
+    for (idx, logs) in parts.into_iter().enumerate() {
+        if parts_len > 1 {
+            eprintln!("part {}/{parts_len}", idx + 1);
+        }
+        if args.clipboard {
+            if idx > 0 {
+                eprintln!("paste and upload the previous part, then press Enter");
+                std::io::stdin().read_line(&mut String::new())?;
+            }
+            let payload = base64::engine::general_purpose::STANDARD.encode(&logs);
+            print!("\x1b]52;c;{payload}\x07");
+            std::io::stdout().flush()?;
+            eprintln!("copied {} bytes to the clipboard", payload.len());
+            continue;
+        }
+
+        let server = args.server.as_deref().unwrap_or_default();
+        let resp = if streaming {
+            post_stream(server, &args, logs)?
+        } else {
+            let Some(resp) = post(server, &args, logs)? else {
+                continue;
+            };
+            resp
+        };
+        for diagnostic in &resp.diagnostics {
+            eprintln!("warning: {diagnostic}");
+        }
+        for skipped in &resp.skipped {
+            eprintln!("skipped already uploaded cast {skipped}");
+        }
+        if resp.appended {
+            eprintln!("appended to existing log");
+        }
+        if resp.duplicate {
+            eprintln!("already uploaded, not stored again");
+        }
+        println!("{}{}", server.trim_end_matches('/'), resp.url);
+    }
+
+    eprintln!(
+        "sent {} cast(s) and {} heartbeat(s): {} bytes raw, {} bytes encoded",
+        summary.casts, summary.heartbeats, summary.raw_bytes, summary.encoded_bytes
+    );
+    Ok(())
+}
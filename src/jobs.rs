@@ -0,0 +1,194 @@
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::models::log::parse_log;
+use crate::models::{AppError, JobMeta, UploadResp, env_or};
+use crate::upload::{Progress, store, strict_error};
+
+/// How often idle workers poll for jobs and running jobs report progress.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Queues an upload and returns the job id and log uuid; the payload is kept in MinIO. Reuses the
+/// job of an earlier request with the same idempotency key.
+pub async fn enqueue(
+    app: &AppState,
+    uuid: Uuid,
+    notes: &str,
+    logs: String,
+    strict: bool,
+    idempotency_key: Option<String>,
+) -> Result<(Uuid, Uuid), AppError> {
+    let earlier = async |key: &str| -> Result<Option<(Uuid, Uuid)>, AppError> {
+        let Some(job) = app.db.query_job_by_key(key).await? else {
+            return Ok(None);
+        };
+        let parse = |id: &str| Uuid::parse_str(id).map_err(|e| AppError::DbCtx(e.into()));
+        Ok(Some((parse(&job.id)?, parse(&job.log_uuid)?)))
+    };
+    if let Some(key) = &idempotency_key
+        && let Some(queued) = earlier(key).await?
+    {
+        return Ok(queued);
+    }
+
+    let id = Uuid::new_v4();
+    app.minio.upload_job_payload(&id, logs).await?;
+    if !app.db.enqueue_job(&id, &uuid, notes, strict, idempotency_key.as_deref()).await? {
+        // Lost a race against a retry of the same request.
+        app.minio.delete_job_payload(&id).await?;
+        let key = idempotency_key.as_deref().unwrap_or_default();
+        return earlier(key).await?.ok_or(AppError::JobNotFound(id));
+    }
+    app.jobs.notify_one();
+    Ok((id, uuid))
+}
+
+/// Starts `UPLOAD_JOB_WORKERS` upload workers, and a sweeper that requeues jobs whose worker
+/// stopped renewing its lease for `UPLOAD_JOB_LEASE_SECS`.
+pub async fn spawn_workers(app: &AppState) -> anyhow::Result<()> {
+    let lease = env_or("UPLOAD_JOB_LEASE_SECS", 60u64).max(POLL_INTERVAL.as_secs() * 3);
+    requeue_expired(app, lease).await?;
+    tokio::spawn({
+        let app = app.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(lease)).await;
+                if let Err(e) = requeue_expired(&app, lease).await {
+                    eprintln!("upload jobs: {e:#}");
+                }
+            }
+        }
+    });
+    // Leases name the process holding them, so other instances can tell live jobs from orphans.
+    let instance = Uuid::new_v4();
+    let workers = env_or("UPLOAD_JOB_WORKERS", 2).max(1);
+    for _ in 0..workers {
+        tokio::spawn(worker(app.clone(), instance));
+    }
+    Ok(())
+}
+
+async fn requeue_expired(app: &AppState, lease: u64) -> anyhow::Result<()> {
+    if app.db.requeue_expired_jobs(lease).await? > 0 {
+        app.jobs.notify_waiters();
+    }
+    Ok(())
+}
+
+async fn worker(app: AppState, instance: Uuid) {
+    loop {
+        match app.db.claim_job(&instance).await {
+            Ok(Some(job)) => run(&app, job, instance).await,
+            Ok(None) => {
+                tokio::select! {
+                    _ = app.jobs.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("upload jobs: {e:#}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Processes a claimed job. Progress reports double as the heartbeat that renews its lease.
+async fn run(app: &AppState, job: JobMeta, instance: Uuid) {
+    let progress = Arc::new(Progress::default());
+    let reporter = tokio::spawn({
+        let (app, id, progress) = (app.clone(), job.id.clone(), progress.clone());
+        async move {
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                let (done, total) = progress.get();
+                if let Err(e) = app.db.update_job_progress(&id, &instance, done, total).await {
+                    eprintln!("upload job {id}: {e:#}");
+                }
+            }
+        }
+    });
+    let outcome = process(app, &job, &progress).await;
+    reporter.abort();
+
+    let (done, total) = progress.get();
+    let recorded = async {
+        app.db.update_job_progress(&job.id, &instance, done, total).await?;
+        let outcome = match outcome {
+            Ok(resp) => Ok(serde_json::to_string(&resp)?),
+            Err(e) => Err(e.to_string()),
+        };
+        // Failed jobs are not retried, so their payload goes too; a requeued job still needs it.
+        if app.db.finish_job(&job.id, &instance, outcome).await? {
+            app.minio.delete_job_payload(&Uuid::parse_str(&job.id)?).await?;
+        }
+        anyhow::Ok(())
+    };
+    if let Err(e) = recorded.await {
+        eprintln!("upload job {}: {e:#}", job.id);
+    }
+}
+
+async fn process(app: &AppState, job: &JobMeta, progress: &Progress) -> Result<UploadResp, AppError> {
+    let id = Uuid::parse_str(&job.id).map_err(|e| AppError::DbCtx(e.into()))?;
+    let uuid = Uuid::parse_str(&job.log_uuid).map_err(|e| AppError::DbCtx(e.into()))?;
+    let logs = app.minio.job_payload(&id).await?;
+    let parsed = app.pool.run(move || parse_log(&logs)).await?;
+    if job.strict && !parsed.diagnostics.is_empty() {
+        return Err(strict_error(&parsed.diagnostics));
+    }
+    store(app, uuid, &job.notes, parsed, job.idempotency_key.clone(), progress).await
+}
+
+#[derive(Serialize)]
+struct JobProgress {
+    done: u32,
+    total: u32,
+}
+
+#[derive(Serialize)]
+struct JobStatus {
+    id: String,
+    uuid: String,
+    state: String,
+    progress: JobProgress,
+    error: Option<String>,
+    /// The upload response once the job is `done`.
+    result: Option<Value>,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: OffsetDateTime,
+}
+
+/// State and progress of an upload job.
+pub async fn job_status(State(app): State<AppState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, AppError> {
+    let job = app.db.query_job(&id).await?.ok_or(AppError::JobNotFound(id))?;
+    let result = job
+        .result
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|e| AppError::DbCtx(e.into()))?;
+    Ok(Json(JobStatus {
+        id: job.id,
+        uuid: job.log_uuid,
+        state: job.state,
+        progress: JobProgress {
+            done: job.progress_done,
+            total: job.progress_total,
+        },
+        error: job.error,
+        result,
+        created_at: job.created_at,
+        updated_at: job.updated_at,
+    }))
+}
@@ -0,0 +1,3 @@
+//! The upload payload format, shared by the server and `src/bin/uploader.rs`.
+
+pub mod log;
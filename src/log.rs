@@ -0,0 +1,669 @@
+use anyhow::{Context, bail};
+use base64::Engine as _;
+use regex::Regex;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+use time::OffsetDateTime;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum Event {
+    Heartbeat(Vec<OffsetDateTime>),
+    Cast {
+        filename: u128,
+        content: Vec<u8>,
+        checksum: Option<String>,
+        offset: Option<u64>,
+    },
+}
+
+/// `[filename, content, sha256?, offset?]`: `sha256` covers the whole uncompressed cast and may be
+/// `null`; `offset` places this chunk within the uncompressed cast when it is split over several lines.
+#[derive(Debug, Deserialize)]
+struct CastPayload(
+    u128,
+    String,
+    #[serde(default)] Option<String>,
+    #[serde(default)] Option<u64>,
+);
+
+/// How an event's base64 payload is compressed, declared by the optional third element of the line.
+/// Lines without it are zstd, as produced before other encodings were accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Zstd,
+    Gzip,
+    Xz,
+    None,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "zstd" => Ok(Encoding::Zstd),
+            "gzip" => Ok(Encoding::Gzip),
+            "xz" => Ok(Encoding::Xz),
+            "none" => Ok(Encoding::None),
+            _ => bail!("Unknown payload encoding {s:?}, expected zstd, gzip, xz or none"),
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Xz => "xz",
+            Encoding::None => "none",
+        })
+    }
+}
+
+impl Encoding {
+    fn decode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut out = Vec::new();
+        match self {
+            Encoding::Zstd => return zstd::stream::decode_all(data),
+            Encoding::Gzip => flate2::read::MultiGzDecoder::new(data).read_to_end(&mut out)?,
+            Encoding::Xz => xz2::read::XzDecoder::new_multi_decoder(data).read_to_end(&mut out)?,
+            Encoding::None => return Ok(data.to_vec()),
+        };
+        Ok(out)
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut out = Vec::new();
+        match self {
+            Encoding::Zstd => return zstd::stream::encode_all(data, 3),
+            Encoding::Gzip => flate2::read::GzEncoder::new(data, flate2::Compression::default()).read_to_end(&mut out)?,
+            Encoding::Xz => xz2::read::XzEncoder::new(data, 6).read_to_end(&mut out)?,
+            Encoding::None => return Ok(data.to_vec()),
+        };
+        Ok(out)
+    }
+}
+
+/// `[kind, payload, encoding?]`
+#[derive(Debug, Deserialize)]
+struct Line(String, Value, #[serde(default)] Option<String>);
+
+impl TryFrom<&str> for Event {
+    type Error = anyhow::Error;
+
+    fn try_from(line: &str) -> anyhow::Result<Self> {
+        let Line(kind, payload, encoding) = serde_json::from_str(line).context("Invalid event")?;
+        let encoding = encoding.as_deref().map(str::parse::<Encoding>).transpose()?.unwrap_or_default();
+
+        match kind.as_str() {
+            "cast" => {
+                let CastPayload(filename, content, checksum, offset) = serde_json::from_value(payload)
+                    .context("cast payload expects [filename, content, sha256?, offset?]")?;
+
+                let compressed = base64::engine::general_purpose::STANDARD
+                    .decode(&content)
+                    .with_context(|| format!("Invalid base64 in cast {filename}"))?;
+                let cast = encoding
+                    .decode(&compressed)
+                    .with_context(|| format!("Failed to decompress cast {filename} with {encoding}"))?;
+                Ok(Event::Cast {
+                    filename,
+                    content: cast,
+                    checksum: checksum.map(|c| c.to_ascii_lowercase()),
+                    offset,
+                })
+            }
+            "heartbeat" => {
+                let content: String =
+                    serde_json::from_value(payload).context("heartbeat payload expects [timestamps]")?;
+                let compressed = base64::engine::general_purpose::STANDARD
+                    .decode(&content)
+                    .context("Invalid base64 in heartbeat payload")?;
+                let raw = encoding
+                    .decode(&compressed)
+                    .with_context(|| format!("Failed to decompress heartbeat payload with {encoding}"))?;
+                Ok(Event::Heartbeat(parse_heartbeats(&raw)?))
+            }
+            _ => bail!("Unknown event type {kind}"),
+        }
+    }
+}
+
+fn encode_payload(raw: &[u8], encoding: Encoding) -> anyhow::Result<String> {
+    let compressed = encoding
+        .encode(raw)
+        .with_context(|| format!("Failed to compress payload with {encoding}"))?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(compressed))
+}
+
+/// Appends the encoding to an event line unless it is the default.
+fn event_line(kind: &str, payload: Value, encoding: Encoding) -> anyhow::Result<String> {
+    let line = match encoding {
+        Encoding::Zstd => json!([kind, payload]),
+        _ => json!([kind, payload, encoding.to_string()]),
+    };
+    Ok(serde_json::to_string(&line)?)
+}
+
+/// Builds the `heartbeat` line for a raw `heartbeat.log`, the inverse of the `heartbeat` arm of `Event::try_from`.
+pub fn encode_heartbeat(raw: &[u8], encoding: Encoding) -> anyhow::Result<String> {
+    event_line("heartbeat", json!(encode_payload(raw, encoding)?), encoding)
+}
+
+/// Builds the `cast` line for a binary cast, the inverse of the `cast` arm of `Event::try_from`.
+pub fn encode_cast(filename: u128, raw: &[u8], encoding: Encoding) -> anyhow::Result<String> {
+    let payload = json!([filename, encode_payload(raw, encoding)?, cast_checksum(raw)]);
+    event_line("cast", payload, encoding)
+}
+
+/// Builds `cast` lines for a binary cast split into chunks of at most `chunk_size` uncompressed bytes,
+/// each carrying its offset so the server can reassemble them in any order.
+pub fn encode_cast_chunks(
+    filename: u128,
+    raw: &[u8],
+    chunk_size: usize,
+    encoding: Encoding,
+) -> anyhow::Result<Vec<String>> {
+    let checksum = cast_checksum(raw);
+    raw.chunks(chunk_size.max(1))
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let offset = (idx * chunk_size.max(1)) as u64;
+            let payload = json!([filename, encode_payload(chunk, encoding)?, checksum, offset]);
+            event_line("cast", payload, encoding)
+        })
+        .collect()
+}
+
+/// SHA-256 of an uncompressed binary cast, as carried in the optional third element of a `cast` event.
+pub fn cast_checksum(raw: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(raw))
+}
+
+/// Fingerprint of an upload's content that does not depend on how it was encoded, chunked or
+/// ordered: SHA-256 over the sorted heartbeats and the sorted `(filename, cast SHA-256)` pairs.
+/// `None` for an upload with nothing in it.
+pub fn content_hash<'a>(
+    heartbeats: &[OffsetDateTime],
+    casts: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Option<String> {
+    let mut heartbeats = heartbeats.iter().map(|t| t.unix_timestamp()).collect::<Vec<_>>();
+    let mut casts = casts.into_iter().collect::<Vec<_>>();
+    if heartbeats.is_empty() && casts.is_empty() {
+        return None;
+    }
+    heartbeats.sort_unstable();
+    casts.sort_unstable();
+
+    let mut hasher = Sha256::new();
+    for ts in heartbeats {
+        hasher.update(ts.to_le_bytes());
+    }
+    for (filename, checksum) in casts {
+        hasher.update(format!("\n{filename}\0{checksum}"));
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Decodes a raw `heartbeat.log`: a sequence of little-endian `u32` unix timestamps.
+pub fn parse_heartbeats(raw: &[u8]) -> anyhow::Result<Vec<OffsetDateTime>> {
+    raw.chunks_exact(4)
+        .map(|chunk| {
+            let ts = u32::from_le_bytes(chunk.try_into()?) as i64;
+            OffsetDateTime::from_unix_timestamp(ts).context("Invalid heartbeat timestamp")
+        })
+        .collect()
+}
+
+/// Extracts the recording timestamp from a cast filename such as `1718000000000.cast`.
+pub fn parse_cast_filename(name: &str) -> anyhow::Result<u128> {
+    let stem = name.split('.').next().unwrap_or_default();
+    stem.parse::<u128>()
+        .with_context(|| format!("cast filename {name:?} is not a timestamp"))
+}
+
+#[derive(Debug)]
+pub struct CastRaw {
+    pub filename: String,
+    pub content: Vec<u8>,
+    /// SHA-256 of `content`, present when the uploader sent one and it matched.
+    pub checksum: Option<String>,
+    /// Whether `content` is the recording as uploaded, to be archived as the cast's original. Not
+    /// so when it was converted from files that are not kept, like a typescript and its timing.
+    pub archive: bool,
+}
+
+static TS_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?Z ").unwrap());
+
+pub fn strip_timestamps<S: AsRef<str>>(input: S) -> String {
+    TS_RE.replace_all(input.as_ref(), "").into_owned()
+}
+
+/// Leading `["part", [upload_id, n, m, checksum]]` line of a paste that carries part `n` of `m` of a
+/// larger upload. `checksum` is the SHA-256 of the remaining lines, see [`part_checksum`].
+#[derive(Debug)]
+pub struct PartHeader {
+    pub upload_id: String,
+    pub part: u32,
+    pub total: u32,
+}
+
+/// Non-empty lines of a part body, joined with `\n` so the checksum survives CRLF and trailing newlines.
+fn part_body<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    lines
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn part_checksum(body: &str) -> String {
+    format!("{:x}", Sha256::digest(body.as_bytes()))
+}
+
+/// Splits a leading `part` line off `buf` and verifies the checksum of the remaining body.
+/// Returns `None` for an ordinary, unchunked payload.
+pub fn split_part(buf: &str) -> anyhow::Result<Option<(PartHeader, String)>> {
+    let buf = strip_timestamps(buf);
+    let mut lines = buf.lines().skip_while(|line| line.trim().is_empty());
+    let Some(first) = lines.next() else {
+        return Ok(None);
+    };
+    let Ok((kind, payload)) = serde_json::from_str::<(String, Value)>(first) else {
+        return Ok(None);
+    };
+    if kind != "part" {
+        return Ok(None);
+    }
+
+    let (upload_id, part, total, checksum): (String, u32, u32, String) =
+        serde_json::from_value(payload).context("part payload expects [upload_id, part, total, checksum]")?;
+    if total == 0 || part == 0 || part > total {
+        bail!("part {part} of {total} of upload {upload_id} is out of range");
+    }
+    let body = part_body(lines);
+    let actual = part_checksum(&body);
+    if actual != checksum {
+        bail!("part {part} of {total} of upload {upload_id} is damaged: checksum {actual} does not match {checksum}");
+    }
+    Ok(Some((
+        PartHeader { upload_id, part, total },
+        body,
+    )))
+}
+
+/// Prefixes `lines` with the `part` line for part `part` of `total`, the inverse of [`split_part`].
+pub fn encode_part(upload_id: &str, part: u32, total: u32, lines: &[String]) -> anyhow::Result<String> {
+    let body = part_body(lines.iter().map(String::as_str));
+    let header = serde_json::to_string(&json!(["part", [upload_id, part, total, part_checksum(&body)]]))?;
+    Ok(format!("{header}\n{body}"))
+}
+
+/// A line of the pasted log that could not be turned into an event.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub kind: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(kind) = &self.kind {
+            write!(f, " ({kind})")?;
+        }
+        write!(f, ": {}", self.errors.join(": "))
+    }
+}
+
+/// Joins the chunks of one cast. Chunks without an offset are concatenated in arrival order; chunks
+/// with offsets are placed by offset, so reordered or repeated lines reassemble to the same bytes.
+fn assemble_chunks(mut chunks: Vec<(Option<u64>, Vec<u8>)>) -> anyhow::Result<Vec<u8>> {
+    if chunks.iter().all(|(offset, _)| offset.is_none()) {
+        return Ok(chunks.into_iter().flat_map(|(_, content)| content).collect());
+    }
+    if chunks.iter().any(|(offset, _)| offset.is_none()) {
+        bail!("mixes chunks with and without offsets");
+    }
+
+    chunks.sort_by_key(|(offset, _)| offset.unwrap_or_default());
+    let mut content = Vec::<u8>::new();
+    for (offset, chunk) in chunks {
+        let offset = offset.unwrap_or_default();
+        if offset > content.len() as u64 {
+            bail!("missing bytes {}..{offset}", content.len());
+        }
+        let offset = offset as usize;
+        let end = offset + chunk.len();
+        let overlap = content.len().min(end) - offset;
+        if content[offset..offset + overlap] != chunk[..overlap] {
+            bail!("conflicting chunks at offset {offset}");
+        }
+        content.extend_from_slice(&chunk[overlap..]);
+    }
+    Ok(content)
+}
+
+/// Incremental counterpart of [`assemble_chunks`] for casts converted while the upload arrives: each
+/// chunk releases the bytes that now continue the ones released before, and chunks that arrive
+/// early wait until the gap before them is filled. Overlaps are checked like [`assemble_chunks`]
+/// does, against the last released bytes, which are kept for as long as the largest chunk seen.
+/// A chunk lying wholly before those must repeat an earlier chunk exactly.
+#[derive(Default)]
+pub struct ChunkStream {
+    with_offsets: Option<bool>,
+    next: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    /// The released bytes just before `next`.
+    tail: Vec<u8>,
+    window: usize,
+    /// SHA-256 of each chunk released whole, by offset and length.
+    released: HashMap<(u64, usize), [u8; 32]>,
+    hasher: Sha256,
+}
+
+impl ChunkStream {
+    pub fn push(&mut self, offset: Option<u64>, chunk: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if *self.with_offsets.get_or_insert(offset.is_some()) != offset.is_some() {
+            bail!("mixes chunks with and without offsets");
+        }
+        let ready = match offset {
+            None => {
+                self.next += chunk.len() as u64;
+                chunk
+            }
+            Some(offset) => {
+                self.window = self.window.max(chunk.len());
+                match self.pending.entry(offset) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(chunk);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let overlap = entry.get().len().min(chunk.len());
+                        if entry.get()[..overlap] != chunk[..overlap] {
+                            bail!("conflicting chunks at offset {offset}");
+                        }
+                        if entry.get().len() < chunk.len() {
+                            entry.insert(chunk);
+                        }
+                    }
+                }
+                let mut ready = Vec::new();
+                while let Some(entry) = self.pending.first_entry() {
+                    if *entry.key() > self.next {
+                        break;
+                    }
+                    let (start, data) = entry.remove_entry();
+                    self.check_overlap(start, &data, &ready)?;
+                    let skip = (self.next - start) as usize;
+                    if skip == 0 {
+                        self.released.insert((start, data.len()), Sha256::digest(&data).into());
+                    }
+                    if skip < data.len() {
+                        ready.extend_from_slice(&data[skip..]);
+                        self.next = start + data.len() as u64;
+                    }
+                }
+                ready
+            }
+        };
+        self.tail.extend_from_slice(&ready);
+        let excess = self.tail.len().saturating_sub(self.window);
+        self.tail.drain(..excess);
+        self.hasher.update(&ready);
+        Ok(ready)
+    }
+
+    /// Compares the part of `data` at `start` that was already released, in `tail` or in `ready`,
+    /// with the bytes released there.
+    fn check_overlap(&self, start: u64, data: &[u8], ready: &[u8]) -> anyhow::Result<()> {
+        let end = self.next.min(start + data.len() as u64);
+        if end <= start {
+            return Ok(());
+        }
+        let released = self.tail.iter().chain(ready);
+        let released_len = (self.tail.len() + ready.len()) as u64;
+        let Some(from) = released_len.checked_sub(self.next - start) else {
+            if end == start + data.len() as u64
+                && self.released.get(&(start, data.len())) == Some(&Sha256::digest(data).into())
+            {
+                return Ok(());
+            }
+            bail!("chunk at offset {start} overlaps bytes converted too long ago to compare");
+        };
+        let overlap = (end - start) as usize;
+        if !released.skip(from as usize).take(overlap).eq(data[..overlap].iter()) {
+            bail!("conflicting chunks at offset {start}");
+        }
+        Ok(())
+    }
+
+    /// Checks that no bytes are missing and returns the SHA-256 of everything released.
+    pub fn finish(self) -> anyhow::Result<String> {
+        if let Some(offset) = self.pending.keys().next() {
+            bail!("missing bytes {}..{offset}", self.next);
+        }
+        Ok(format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedLog {
+    pub heartbeats: Vec<OffsetDateTime>,
+    pub casts: Vec<CastRaw>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parses one non-empty line of a log, already stripped of timestamps.
+pub fn parse_line(lineno: usize, line: &str) -> Result<Event, Diagnostic> {
+    Event::try_from(line).map_err(|e| {
+        let kind = serde_json::from_str::<(String, IgnoredAny)>(line).ok().map(|(kind, _)| kind);
+        Diagnostic {
+            line: lineno,
+            kind,
+            errors: e.chain().map(|c| c.to_string()).collect(),
+        }
+    })
+}
+
+pub fn parse_log(buf: &str) -> ParsedLog {
+    let buf = strip_timestamps(buf);
+    let mut diagnostics = Vec::new();
+    let events = buf
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(idx, line)| match parse_line(idx + 1, line) {
+            Ok(event) => Some((idx + 1, event)),
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let hbs_raw = events
+        .iter()
+        .filter_map(|(_, x)| match x {
+            Event::Heartbeat(times) => Some(times.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
+    #[derive(Default)]
+    struct CastParts {
+        first_line: usize,
+        chunks: Vec<(Option<u64>, Vec<u8>)>,
+        checksum: Option<(usize, String)>,
+    }
+    let mut casts_map = BTreeMap::<u128, CastParts>::new();
+    events
+        .into_iter()
+        .filter_map(|(line, x)| match x {
+            Event::Cast {
+                filename,
+                content,
+                checksum,
+                offset,
+            } => Some((line, filename, content, checksum, offset)),
+            _ => None,
+        })
+        .for_each(|(line, filename, content, checksum, offset)| {
+            let parts = casts_map.entry(filename).or_insert_with(|| CastParts {
+                first_line: line,
+                ..Default::default()
+            });
+            parts.chunks.push((offset, content));
+            if let Some(checksum) = checksum {
+                parts.checksum.get_or_insert((line, checksum));
+            }
+        });
+
+    let casts_raw = casts_map
+        .into_iter()
+        .filter_map(|(filename, parts)| {
+            let content = match assemble_chunks(parts.chunks) {
+                Ok(content) => content,
+                Err(e) => {
+                    diagnostics.push(Diagnostic {
+                        line: parts.first_line,
+                        kind: Some("cast".to_string()),
+                        errors: vec![format!("cast {filename}: {e}")],
+                    });
+                    return None;
+                }
+            };
+            let checksum = match parts.checksum {
+                Some((line, expected)) => {
+                    let actual = cast_checksum(&content);
+                    if actual != expected {
+                        diagnostics.push(Diagnostic {
+                            line,
+                            kind: Some("cast".to_string()),
+                            errors: vec![format!(
+                                "cast {filename} is damaged: sha256 {actual} does not match {expected}"
+                            )],
+                        });
+                        return None;
+                    }
+                    Some(actual)
+                }
+                None => None,
+            };
+            Some(CastRaw {
+                filename: format!("{filename}"),
+                content,
+                checksum,
+                archive: true,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    ParsedLog {
+        heartbeats: hbs_raw,
+        casts: casts_raw,
+        diagnostics,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: &[(u64, &str)]) -> Vec<(Option<u64>, Vec<u8>)> {
+        parts.iter().map(|&(offset, s)| (Some(offset), s.as_bytes().to_vec())).collect()
+    }
+
+    fn stream(parts: &[(u64, &str)]) -> anyhow::Result<Vec<u8>> {
+        let mut stream = ChunkStream::default();
+        let mut out = Vec::new();
+        for (offset, chunk) in chunks(parts) {
+            out.extend(stream.push(offset, chunk)?);
+        }
+        stream.finish()?;
+        Ok(out)
+    }
+
+    #[test]
+    fn chunks_reassemble_in_any_order() {
+        let parts = [(6, "ghi"), (0, "abc"), (3, "def"), (3, "def")];
+        assert_eq!(assemble_chunks(chunks(&parts)).unwrap(), b"abcdefghi");
+        assert_eq!(stream(&parts).unwrap(), b"abcdefghi");
+    }
+
+    #[test]
+    fn chunks_without_offsets_keep_arrival_order() {
+        let parts = vec![(None, b"abc".to_vec()), (None, b"def".to_vec())];
+        assert_eq!(assemble_chunks(parts).unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn chunks_with_gap_are_rejected() {
+        let parts = [(0, "abc"), (6, "ghi")];
+        assert!(assemble_chunks(chunks(&parts)).unwrap_err().to_string().contains("missing bytes 3..6"));
+        assert!(stream(&parts).unwrap_err().to_string().contains("missing bytes 3..6"));
+    }
+
+    #[test]
+    fn chunk_at_huge_offset_is_a_gap() {
+        let parts = [(0, "abc"), (u64::MAX - 1, "def")];
+        let missing = format!("missing bytes 3..{}", u64::MAX - 1);
+        assert!(assemble_chunks(chunks(&parts)).unwrap_err().to_string().contains(&missing));
+        assert!(stream(&parts).unwrap_err().to_string().contains(&missing));
+    }
+
+    #[test]
+    fn mixed_offsets_are_rejected() {
+        let parts = vec![(None, b"abc".to_vec()), (Some(3), b"def".to_vec())];
+        assert!(assemble_chunks(parts).is_err());
+        let mut stream = ChunkStream::default();
+        stream.push(None, b"abc".to_vec()).unwrap();
+        assert!(stream.push(Some(3), b"def".to_vec()).is_err());
+    }
+
+    #[test]
+    fn conflicting_overlap_is_rejected() {
+        for parts in [
+            &[(0, "abcdef"), (3, "XYZghi")][..],
+            &[(3, "XYZghi"), (0, "abcdef")],
+            &[(0, "abc"), (3, "defghi"), (3, "defXYZ")],
+        ] {
+            assert!(assemble_chunks(chunks(parts)).unwrap_err().to_string().contains("conflicting"));
+            assert!(stream(parts).unwrap_err().to_string().contains("conflicting"));
+        }
+    }
+
+    #[test]
+    fn matching_overlap_is_accepted() {
+        let parts = [(0, "abcdef"), (3, "defghi"), (4, "ef"), (0, "abcdef")];
+        assert_eq!(assemble_chunks(chunks(&parts)).unwrap(), b"abcdefghi");
+        assert_eq!(stream(&parts).unwrap(), b"abcdefghi");
+    }
+
+    #[test]
+    fn chunked_cast_round_trips_through_parse_log() {
+        let raw = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        let mut lines = encode_cast_chunks(1_718_000_000_000, &raw, 3000, Encoding::Gzip).unwrap();
+        lines.reverse();
+        let parsed = parse_log(&lines.join("\n"));
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.casts.len(), 1);
+        assert_eq!(parsed.casts[0].content, raw);
+        assert_eq!(parsed.casts[0].checksum.as_deref(), Some(cast_checksum(&raw).as_str()));
+    }
+}
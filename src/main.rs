@@ -1,11 +1,14 @@
 use anyhow::Context;
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
 use axum::routing::{delete, get, post};
 use dotenvy::dotenv;
+use std::sync::Arc;
+use tokio::sync::Notify;
 use tower_http::services::ServeDir;
 
 mod models;
-use models::{MariaDB, MinIO};
+use models::{MariaDB, MinIO, WorkPool, env_or};
 
 mod index;
 use index::index;
@@ -14,7 +17,19 @@ mod list;
 use list::list;
 
 mod upload;
-use upload::upload;
+use upload::{preview, upload, upload_raw};
+
+mod stream;
+use stream::upload_stream;
+
+mod jobs;
+use jobs::{job_status, spawn_workers};
+
+mod reprocess;
+use reprocess::{admin_reprocess, reprocess};
+
+mod search;
+use search::{search, search_api};
 
 mod view;
 use view::view;
@@ -35,6 +50,9 @@ use s3::s3_proxy;
 struct AppState {
     db: MariaDB,
     minio: MinIO,
+    pool: WorkPool,
+    /// Wakes an idle upload worker when a job is queued.
+    jobs: Arc<Notify>,
 }
 
 #[tokio::main]
@@ -43,24 +61,57 @@ async fn main() -> anyhow::Result<()> {
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let address = format!("0.0.0.0:{port}");
     let dir = std::env::var("STATIC_DIR").unwrap();
-
-    println!("Listening on {address}");
+    let upload_limit = env_or("UPLOAD_LIMIT_MB", 256usize);
 
     let db = MariaDB::new().await.context("init DB")?;
     let minio = MinIO::new().await.context("init MinIO")?;
 
-    let state = AppState { db, minio };
+    let pool = WorkPool::new();
+
+    let state = AppState {
+        db,
+        minio,
+        pool,
+        jobs: Arc::new(Notify::new()),
+    };
+
+    // `pty-replay-web reprocess [uuid]` converts archived casts again and exits.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["reprocess", ref rest @ ..] => {
+            let uuid = match rest {
+                [] => None,
+                [uuid] => Some(uuid::Uuid::parse_str(uuid).context("invalid log uuid")?),
+                _ => anyhow::bail!("usage: pty-replay-web reprocess [uuid]"),
+            };
+            let report = reprocess(&state, uuid).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        _ => anyhow::bail!("unknown command {:?}, expected `reprocess [uuid]`", args.join(" ")),
+    }
+
+    spawn_workers(&state).await.context("start upload workers")?;
 
     let api_router = Router::new()
         .route("/mark", post(add_mark))
         .route("/mark", delete(del_mark))
         .route("/note", post(note_update))
         .route("/upload", post(upload))
-        .route("/visible", post(visible));
+        .route("/upload/preview", post(preview))
+        .route("/upload/raw", post(upload_raw))
+        .route("/upload/stream", post(upload_stream))
+        .route("/jobs/{id}", get(job_status))
+        .route("/search", get(search_api))
+        .route("/visible", post(visible))
+        .route("/admin/reprocess", post(admin_reprocess))
+        .layer(DefaultBodyLimit::max(upload_limit * 1024 * 1024));
 
     let core_router = Router::new()
         .route("/", get(index))
         .route("/list", get(list))
+        .route("/search", get(search))
         .route("/view/{id}", get(view))
         .nest("/api", api_router);
 
@@ -70,6 +121,7 @@ async fn main() -> anyhow::Result<()> {
         .route("/s3/{bucket}/{*key}", get(s3_proxy))
         .with_state(state);
 
+    println!("Listening on {address}");
     let listener = tokio::net::TcpListener::bind(address).await.unwrap();
 
     axum::serve(listener, app).await.context("service")
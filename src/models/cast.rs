@@ -0,0 +1,868 @@
+use anyhow::{Context, bail};
+use binrw::BinRead;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use time::Duration;
+use unsigned_varint::io::read_u32;
+
+use super::env_or;
+use super::commands::{Command, LineEditor};
+use super::import::{self, Format};
+use super::text::{OutputText, TextChunk};
+
+/// How event timestamps are encoded in the binary stream.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeFormat {
+    /// `f32` seconds since the start of the recording (v0 and v1).
+    F32Seconds,
+    /// `u64` microseconds since the start of the recording (v2).
+    U64Micros,
+}
+
+/// A decoded event; `elapsed` is always microseconds since the start of the recording.
+#[derive(Debug)]
+pub enum Event {
+    Input { elapsed: u64, data: Vec<u8> },
+    Output { elapsed: u64, data: Vec<u8> },
+    Resize { elapsed: u64, cols: u16, rows: u16 },
+    /// Any other asciicast event code (markers, exit status), passed through unchanged.
+    Other { elapsed: u64, code: String, data: String },
+}
+
+fn micros_to_seconds(micros: u64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+impl Event {
+    fn to_json(&self) -> anyhow::Result<String> {
+        match self {
+            Event::Input { elapsed, data } => {
+                serde_json::to_string(&json!([micros_to_seconds(*elapsed), "i", String::from_utf8_lossy(data)]))
+                    .context("failed to serialize input event")
+            }
+            Event::Output { elapsed, data } => {
+                serde_json::to_string(&json!([micros_to_seconds(*elapsed), "o", String::from_utf8_lossy(data)]))
+                    .context("failed to serialize output event")
+            }
+            Event::Resize { elapsed, cols, rows } => {
+                serde_json::to_string(&json!([micros_to_seconds(*elapsed), "r", format!("{}x{}", cols, rows)]))
+                    .context("failed to serialize resize event")
+            }
+            Event::Other { elapsed, code, data } => {
+                serde_json::to_string(&json!([micros_to_seconds(*elapsed), code, data]))
+                    .with_context(|| format!("failed to serialize {code} event"))
+            }
+        }
+    }
+    fn get_elapsed(&self) -> u64 {
+        match self {
+            Event::Input { elapsed, .. } => *elapsed,
+            Event::Output { elapsed, .. } => *elapsed,
+            Event::Resize { elapsed, .. } => *elapsed,
+            Event::Other { elapsed, .. } => *elapsed,
+        }
+    }
+    fn set_elapsed(&mut self, new: u64) {
+        match self {
+            Event::Input { elapsed, .. }
+            | Event::Output { elapsed, .. }
+            | Event::Resize { elapsed, .. }
+            | Event::Other { elapsed, .. } => *elapsed = new,
+        }
+    }
+}
+
+impl BinRead for Event {
+    type Args<'a> = (TimeFormat,);
+    fn read_options<R: std::io::Read + binrw::io::Seek>(
+        reader: &mut R,
+        _endian: binrw::Endian,
+        (format,): Self::Args<'_>,
+    ) -> binrw::BinResult<Self> {
+        let elapsed = match format {
+            TimeFormat::F32Seconds => (f64::from(f32::read_le(reader)?) * 1_000_000.0).round().max(0.0) as u64,
+            TimeFormat::U64Micros => u64::read_le(reader)?,
+        };
+        let kind = u8::read_le(reader)?;
+
+        Ok(match kind {
+            0 | 1 => {
+                let len = read_u32(&mut *reader).map_err(|e| binrw::Error::Io(e.into()))? as usize;
+                let mut data = vec![0; len];
+                reader.read_exact(&mut data)?;
+                if kind == 0 {
+                    Event::Input { elapsed, data }
+                } else {
+                    Event::Output { elapsed, data }
+                }
+            }
+            2 => {
+                let rows = u16::read_le(reader)?;
+                let cols = u16::read_le(reader)?;
+                Event::Resize { elapsed, cols, rows }
+            }
+            _ => {
+                return Err(binrw::Error::AssertFail {
+                    pos: reader.stream_position()?,
+                    message: format!("unknown kind {kind}"),
+                });
+            }
+        })
+    }
+}
+
+/// Decodes `buf` lossily, returning the text, any incomplete trailing sequence, and whether anything
+/// was replaced.
+fn decode_utf8(mut buf: &[u8]) -> (String, Vec<u8>, bool) {
+    let mut text = String::with_capacity(buf.len());
+    let mut replaced = false;
+    loop {
+        match std::str::from_utf8(buf) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return (text, Vec::new(), replaced);
+            }
+            Err(e) => {
+                let (valid, rest) = buf.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        replaced = true;
+                        buf = &rest[len..];
+                    }
+                    None => return (text, rest.to_vec(), replaced),
+                }
+            }
+        }
+    }
+}
+
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+const DEFAULT_SHELL: &str = "/bin/bash";
+const DEFAULT_TERM: &str = "xterm-color";
+
+/// Leading bytes of a versioned cast, followed by a version byte. Headerless "v0" casts start with
+/// the timestamp.
+///
+/// - v1: [`CastHeaderV1`], events timed in `f32` seconds
+/// - v2: [`CastHeaderV1`], events timed in `u64` microseconds
+pub const CAST_MAGIC: &[u8; 4] = b"PTYR";
+
+#[binrw::parser(reader)]
+fn varint_string() -> binrw::BinResult<String> {
+    let len = read_u32(&mut *reader).map_err(|e| binrw::Error::Io(e.into()))? as usize;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[derive(Debug, BinRead)]
+#[brw(little)]
+struct CastHeaderV0 {
+    ts: u128,
+}
+
+/// Header shared by v1 and v2; v2 only changes the event timestamp encoding.
+#[derive(Debug, BinRead)]
+#[brw(little)]
+struct CastHeaderV1 {
+    ts: u128,
+    cols: u16,
+    rows: u16,
+    #[br(parse_with = varint_string)]
+    term: String,
+    #[br(parse_with = varint_string)]
+    shell: String,
+    #[br(parse_with = varint_string)]
+    hostname: String,
+    #[br(parse_with = varint_string)]
+    command: String,
+}
+
+/// Recording metadata, whichever format it came from. `ts` is milliseconds since the unix epoch.
+#[derive(Debug, Default)]
+pub struct CastHeader {
+    pub ts: u128,
+    pub size: Option<(u16, u16)>,
+    pub term: Option<String>,
+    pub shell: Option<String>,
+    pub hostname: Option<String>,
+    pub command: Option<String>,
+}
+
+impl CastHeader {
+    fn read<R: std::io::Read + std::io::Seek>(reader: &mut R) -> anyhow::Result<(Self, TimeFormat)> {
+        let mut magic = [0u8; 4];
+        let versioned = reader.read_exact(&mut magic).is_ok() && &magic == CAST_MAGIC;
+        if !versioned {
+            reader.rewind()?;
+            let v0 = CastHeaderV0::read(reader).context("failed to read cast header")?;
+            let header = Self {
+                ts: v0.ts,
+                ..Default::default()
+            };
+            return Ok((header, TimeFormat::F32Seconds));
+        }
+
+        let version = u8::read_le(reader).context("failed to read cast version")?;
+        match version {
+            1 | 2 => {
+                let v1 = CastHeaderV1::read(reader).with_context(|| format!("failed to read v{version} cast header"))?;
+                let non_empty = |s: String| (!s.is_empty()).then_some(s);
+                let header = Self {
+                    ts: v1.ts,
+                    size: (v1.cols > 0 && v1.rows > 0).then_some((v1.cols, v1.rows)),
+                    term: non_empty(v1.term),
+                    shell: non_empty(v1.shell),
+                    hostname: non_empty(v1.hostname),
+                    command: non_empty(v1.command),
+                };
+                let time_format = if version == 1 {
+                    TimeFormat::F32Seconds
+                } else {
+                    TimeFormat::U64Micros
+                };
+                Ok((header, time_format))
+            }
+            _ => bail!("unsupported cast format version {version}"),
+        }
+    }
+}
+
+/// Where and why decoding of a salvaged cast stopped.
+#[derive(Debug, Clone, Serialize)]
+pub struct Truncation {
+    pub offset: u64,
+    pub reason: String,
+}
+
+pub struct CastPartial {
+    pub timestamp: i64,
+    pub width: u16,
+    pub height: u16,
+    pub duration: Duration,
+    pub active_duration: Duration,
+    /// Active milliseconds in each minute of the recording.
+    pub activity: Vec<u32>,
+    pub commands: Vec<Command>,
+    /// Printed text, for search.
+    pub text: Vec<TextChunk>,
+    pub event_count: u32,
+    pub repaired_events: u32,
+    pub truncated: Option<Truncation>,
+    pub content: String,
+}
+
+/// Gaps shorter than `ACTIVE_IDLE_SECS` count as active time.
+fn idle_threshold() -> u64 {
+    env_or("ACTIVE_IDLE_SECS", 30u64) * 1_000_000
+}
+
+const MINUTE: u64 = 60_000_000;
+
+/// Caps the activity histogram at a week.
+const MAX_ACTIVITY_MINUTES: usize = 7 * 24 * 60;
+
+/// A decoded recording, before it is measured and written out as asciicast v3.
+#[derive(Debug)]
+pub struct Recording {
+    pub header: CastHeader,
+    pub events: Vec<Event>,
+    pub truncated: Option<Truncation>,
+}
+
+fn read_binary(src: Vec<u8>, salvage: bool) -> anyhow::Result<Recording> {
+    let length = src.len();
+    let mut cur = binrw::io::Cursor::new(src);
+    let (header, time_format) = CastHeader::read(&mut cur)?;
+    let mut events = Vec::new();
+    let mut truncated = None;
+    while (cur.position() as usize) < length {
+        let offset = cur.position();
+        match Event::read_le_args(&mut cur, (time_format,)) {
+            Ok(event) => events.push(event),
+            Err(e) if salvage => {
+                truncated = Some(Truncation {
+                    offset,
+                    reason: e.to_string(),
+                });
+                break;
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to decode event at byte {offset}")),
+        }
+    }
+    Ok(Recording {
+        header,
+        events,
+        truncated,
+    })
+}
+
+/// Converts a binary, asciicast or ttyrec cast into asciicast v3. With `salvage`, a corrupt event
+/// ends the cast instead of failing it.
+pub fn convert_cast(src: Vec<u8>, salvage: bool) -> anyhow::Result<CastPartial> {
+    let recording = match import::detect(&src) {
+        Format::Binary => read_binary(src, salvage)?,
+        Format::Asciicast => import::read_asciicast(&src)?,
+        Format::Ttyrec => import::read_ttyrec(&src)?,
+    };
+    finish(recording)
+}
+
+/// Converts a `script -t` typescript and its timing file into an asciicast v3 document.
+pub fn convert_typescript(typescript: &[u8], timing: &[u8]) -> anyhow::Result<CastPartial> {
+    finish(import::read_typescript(typescript, timing)?)
+}
+
+/// Measures a recording and serializes it as asciicast v3.
+fn finish(recording: Recording) -> anyhow::Result<CastPartial> {
+    let mut converter = Converter::new(recording.header)?;
+    for event in recording.events {
+        converter.push(event)?;
+    }
+    converter.finish(recording.truncated)
+}
+
+fn header_json(header: &CastHeader, width: u16, height: u16) -> anyhow::Result<String> {
+    let term = header.term.as_deref().unwrap_or(DEFAULT_TERM);
+    let mut env = json!({
+        "SHELL": header.shell.as_deref().unwrap_or(DEFAULT_SHELL),
+        "TERM": term,
+    });
+    if let Some(hostname) = &header.hostname {
+        env["HOSTNAME"] = json!(hostname);
+    }
+    let mut json = json!({
+        "version": 3,
+        "term": {
+            "cols": width,
+            "rows": height,
+            "type": term
+        },
+        "timestamp": header.ts,
+        "env": env,
+    });
+    if let Some(command) = &header.command {
+        json["command"] = json!(command);
+    }
+    serde_json::to_string(&json).context("failed to serialize header")
+}
+
+/// Writes events as asciicast v3 lines one at a time, measuring the recording on the way.
+///
+/// A UTF-8 sequence split across events is carried over to the next event of the same kind, which
+/// holds back the split event and everything after it; other invalid bytes become U+FFFD.
+struct Converter {
+    header: CastHeader,
+    /// Known once the header or the first resize gives it; the header line waits for it.
+    size: Option<(u16, u16)>,
+    queue: VecDeque<(u64, Event)>,
+    /// Incomplete UTF-8 tail for input and output, and the event it was cut from.
+    tails: [Vec<u8>; 2],
+    holders: [Option<u64>; 2],
+    seq: u64,
+    /// Event lines written before the size is known.
+    early: Vec<u8>,
+    out: Vec<u8>,
+    prev_elapsed: u64,
+    /// Time of the last input or output event, the start of the gap before the next one.
+    last_activity: Option<u64>,
+    idle: u64,
+    duration: u64,
+    active_duration: u64,
+    activity: Vec<u32>,
+    commands: LineEditor,
+    text: OutputText,
+    event_count: u32,
+    repaired_events: u32,
+}
+
+impl Converter {
+    fn new(header: CastHeader) -> anyhow::Result<Self> {
+        let mut converter = Self {
+            size: None,
+            header,
+            queue: VecDeque::new(),
+            tails: Default::default(),
+            holders: [None, None],
+            seq: 0,
+            early: Vec::new(),
+            out: Vec::new(),
+            prev_elapsed: 0,
+            last_activity: None,
+            idle: idle_threshold(),
+            duration: 0,
+            active_duration: 0,
+            activity: Vec::new(),
+            commands: LineEditor::default(),
+            text: OutputText::default(),
+            event_count: 0,
+            repaired_events: 0,
+        };
+        if let Some(size) = converter.header.size {
+            converter.set_size(size)?;
+        }
+        Ok(converter)
+    }
+
+    fn set_size(&mut self, (width, height): (u16, u16)) -> anyhow::Result<()> {
+        self.size = Some((width, height));
+        self.out.extend_from_slice(header_json(&self.header, width, height)?.as_bytes());
+        self.out.push(b'\n');
+        self.out.append(&mut self.early);
+        Ok(())
+    }
+
+    fn push(&mut self, mut event: Event) -> anyhow::Result<()> {
+        let seq = self.seq;
+        self.seq += 1;
+        self.event_count += 1;
+
+        let payload = match &mut event {
+            Event::Input { data, .. } => Some((0, data)),
+            Event::Output { data, .. } => Some((1, data)),
+            Event::Resize { .. } | Event::Other { .. } => None,
+        };
+        if let Some((slot, data)) = payload {
+            let carried = !self.tails[slot].is_empty();
+            let mut buf = std::mem::take(&mut self.tails[slot]);
+            buf.append(data);
+
+            let (text, tail, replaced) = decode_utf8(&buf);
+            *data = text.into_bytes();
+            if carried || replaced || !tail.is_empty() {
+                self.repaired_events += 1;
+            }
+            self.holders[slot] = (!tail.is_empty()).then_some(seq);
+            self.tails[slot] = tail;
+        }
+
+        self.queue.push_back((seq, event));
+        self.drain()
+    }
+
+    fn drain(&mut self) -> anyhow::Result<()> {
+        while let Some((seq, _)) = self.queue.front() {
+            if self.holders.contains(&Some(*seq)) {
+                break;
+            }
+            let (_, event) = self.queue.pop_front().context("queue is not empty")?;
+            self.emit(event)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, mut event: Event) -> anyhow::Result<()> {
+        let elapsed = event.get_elapsed();
+        if matches!(event, Event::Output { .. }) {
+            self.duration = elapsed;
+        }
+        if matches!(event, Event::Input { .. } | Event::Output { .. }) {
+            if let Some(prev) = self.last_activity
+                && elapsed.saturating_sub(prev) < self.idle
+            {
+                self.add_active(prev, elapsed);
+            }
+            self.last_activity = Some(elapsed);
+        }
+        match &event {
+            Event::Input { data, .. } => self.commands.input(elapsed, data),
+            Event::Output { data, .. } => {
+                self.commands.output(elapsed, data);
+                self.text.output(elapsed, data);
+            }
+            Event::Resize { .. } | Event::Other { .. } => {}
+        }
+
+        if let (None, Event::Resize { cols, rows, .. }) = (self.size, &event) {
+            self.set_size((*cols, *rows))?;
+        }
+
+        event.set_elapsed(elapsed.saturating_sub(self.prev_elapsed));
+        self.prev_elapsed = elapsed;
+        let target = if self.size.is_some() { &mut self.out } else { &mut self.early };
+        target.extend_from_slice(event.to_json()?.as_bytes());
+        target.push(b'\n');
+        Ok(())
+    }
+
+    /// Counts `start..end` as active, splitting it over the minutes it spans.
+    fn add_active(&mut self, start: u64, end: u64) {
+        self.active_duration += end.saturating_sub(start);
+        let mut t = start;
+        while t < end {
+            let minute = (t / MINUTE) as usize;
+            if minute >= MAX_ACTIVITY_MINUTES {
+                break;
+            }
+            let next = ((minute as u64 + 1) * MINUTE).min(end);
+            if self.activity.len() <= minute {
+                self.activity.resize(minute + 1, 0);
+            }
+            self.activity[minute] += ((next - t) / 1000) as u32;
+            t = next;
+        }
+    }
+
+    /// Output that is ready to be written out.
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Flushes held-back events and returns the measurements with whatever output is left.
+    fn finish(mut self, truncated: Option<Truncation>) -> anyhow::Result<CastPartial> {
+        for slot in 0..2 {
+            let Some(seq) = self.holders[slot].take() else {
+                continue;
+            };
+            if let Some((_, Event::Input { data, .. } | Event::Output { data, .. })) =
+                self.queue.iter_mut().find(|(s, _)| *s == seq)
+            {
+                data.extend_from_slice(char::REPLACEMENT_CHARACTER.to_string().as_bytes());
+            }
+        }
+        self.drain()?;
+        if self.size.is_none() {
+            self.set_size(DEFAULT_SIZE)?;
+        }
+        let (width, height) = self.size.unwrap_or(DEFAULT_SIZE);
+
+        Ok(CastPartial {
+            timestamp: (self.header.ts / 1000) as i64,
+            width,
+            height,
+            duration: Duration::microseconds(self.duration as i64),
+            active_duration: Duration::microseconds(self.active_duration as i64),
+            activity: self.activity,
+            commands: self.commands.finish(),
+            text: self.text.finish(),
+            event_count: self.event_count,
+            repaired_events: self.repaired_events,
+            truncated,
+            content: String::from_utf8(self.out).context("converted cast is not valid UTF-8")?,
+        })
+    }
+}
+
+/// How much of a versioned cast is buffered before its header is parsed; headers are far smaller.
+const HEADER_WINDOW: usize = 64 * 1024;
+
+enum StreamState {
+    /// Too few bytes seen to tell the format.
+    Sniffing,
+    /// Possibly an imported recording; converted with [`convert_cast`] once complete.
+    Buffered,
+    Header,
+    Events { time_format: TimeFormat, converter: Converter },
+    /// Salvage stopped decoding; further bytes are ignored.
+    Truncated { converter: Converter, truncation: Truncation },
+    Done,
+}
+
+/// Incremental [`convert_cast`] for binary casts of any version; imported formats are buffered
+/// until [`CastStream::finish`].
+pub struct CastStream {
+    salvage: bool,
+    /// Bytes received but not decoded yet.
+    buf: Vec<u8>,
+    /// Offset of `buf[0]` within the cast.
+    consumed: u64,
+    state: StreamState,
+}
+
+impl CastStream {
+    pub fn new(salvage: bool) -> Self {
+        Self {
+            salvage,
+            buf: Vec::new(),
+            consumed: 0,
+            state: StreamState::Sniffing,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        if !matches!(self.state, StreamState::Truncated { .. }) {
+            self.buf.extend_from_slice(bytes);
+        }
+        self.advance(false)
+    }
+
+    /// Converted output produced so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        match &mut self.state {
+            StreamState::Events { converter, .. } | StreamState::Truncated { converter, .. } => converter.take_output(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// `content` of the result holds only output not yet taken.
+    pub fn finish(mut self) -> anyhow::Result<CastPartial> {
+        self.advance(true)?;
+        match std::mem::replace(&mut self.state, StreamState::Done) {
+            StreamState::Buffered => convert_cast(std::mem::take(&mut self.buf), self.salvage),
+            StreamState::Events { converter, .. } => converter.finish(None),
+            StreamState::Truncated { converter, truncation } => converter.finish(Some(truncation)),
+            StreamState::Sniffing | StreamState::Header | StreamState::Done => bail!("cast ended before its header"),
+        }
+    }
+
+    fn advance(&mut self, eof: bool) -> anyhow::Result<()> {
+        loop {
+            match &mut self.state {
+                StreamState::Sniffing => {
+                    let versioned = self.buf.starts_with(CAST_MAGIC);
+                    if !versioned && self.buf.len() < HEADER_WINDOW && !eof {
+                        return Ok(());
+                    }
+                    // Whatever might be an import is left to `convert_cast`, which sees the whole file.
+                    self.state = if versioned || !(eof || import::may_be_import(&self.buf)) {
+                        StreamState::Header
+                    } else {
+                        StreamState::Buffered
+                    };
+                }
+                StreamState::Header => {
+                    if self.buf.len() < HEADER_WINDOW && !eof {
+                        return Ok(());
+                    }
+                    let mut cur = binrw::io::Cursor::new(&self.buf[..]);
+                    let (header, time_format) = CastHeader::read(&mut cur)?;
+                    self.consume(cur.position() as usize);
+                    self.state = StreamState::Events {
+                        time_format,
+                        converter: Converter::new(header)?,
+                    };
+                }
+                StreamState::Events { time_format, converter } => {
+                    let mut cur = binrw::io::Cursor::new(&self.buf[..]);
+                    let mut decoded = 0;
+                    let mut failure = None;
+                    while decoded < self.buf.len() {
+                        match Event::read_le_args(&mut cur, (*time_format,)) {
+                            Ok(event) => {
+                                converter.push(event)?;
+                                decoded = cur.position() as usize;
+                            }
+                            Err(binrw::Error::Io(e)) if !eof && e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                            // Positions in decode errors are relative to `buf`; report them within the cast.
+                            Err(binrw::Error::AssertFail { pos, message }) => {
+                                failure = Some(binrw::Error::AssertFail {
+                                    pos: pos + self.consumed,
+                                    message,
+                                });
+                                break;
+                            }
+                            Err(e) => {
+                                failure = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                    let offset = self.consumed + decoded as u64;
+                    self.consume(decoded);
+                    let Some(e) = failure else {
+                        return Ok(());
+                    };
+                    if !self.salvage {
+                        return Err(e).with_context(|| format!("failed to decode event at byte {offset}"));
+                    }
+                    let StreamState::Events { converter, .. } = std::mem::replace(&mut self.state, StreamState::Done)
+                    else {
+                        unreachable!()
+                    };
+                    self.buf = Vec::new();
+                    self.state = StreamState::Truncated {
+                        converter,
+                        truncation: Truncation {
+                            offset,
+                            reason: e.to_string(),
+                        },
+                    };
+                }
+                StreamState::Buffered | StreamState::Truncated { .. } | StreamState::Done => return Ok(()),
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buf.drain(..len);
+        self.consumed += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A v2 binary cast of `(elapsed µs, kind, data)` events.
+    fn v2_cast(events: &[(u64, u8, &[u8])]) -> Vec<u8> {
+        let mut buf = CAST_MAGIC.to_vec();
+        buf.push(2);
+        buf.extend_from_slice(&1_700_000_000_000u128.to_le_bytes());
+        buf.extend_from_slice(&100u16.to_le_bytes());
+        buf.extend_from_slice(&30u16.to_le_bytes());
+        for s in ["xterm-256color", "/bin/bash", "host", ""] {
+            buf.extend_from_slice(unsigned_varint::encode::u32(s.len() as u32, &mut Default::default()));
+            buf.extend_from_slice(s.as_bytes());
+        }
+        for (elapsed, kind, data) in events {
+            buf.extend_from_slice(&elapsed.to_le_bytes());
+            buf.push(*kind);
+            buf.extend_from_slice(unsigned_varint::encode::u32(data.len() as u32, &mut Default::default()));
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+
+    /// Event payloads of a converted cast, skipping the header line.
+    fn payloads(content: &str) -> Vec<String> {
+        content
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                event[2].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_utf8_is_carried_to_next_event() {
+        let cast = v2_cast(&[(0, 1, b"a\xc3"), (1_000, 1, b"\xa9b")]);
+        let partial = convert_cast(cast, false).unwrap();
+        assert_eq!(payloads(&partial.content), ["a", "\u{e9}b"]);
+        assert_eq!(partial.repaired_events, 2);
+    }
+
+    #[test]
+    fn split_utf8_waits_across_other_kinds() {
+        let cast = v2_cast(&[(0, 1, b"\xe2\x82"), (500, 0, b"x"), (1_000, 1, b"\xac")]);
+        let partial = convert_cast(cast, false).unwrap();
+        assert_eq!(payloads(&partial.content), ["", "x", "\u{20ac}"]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let cast = v2_cast(&[(0, 1, b"a\xffb"), (1_000, 1, b"ok")]);
+        let partial = convert_cast(cast, false).unwrap();
+        assert_eq!(payloads(&partial.content), ["a\u{fffd}b", "ok"]);
+        assert_eq!(partial.repaired_events, 1);
+    }
+
+    #[test]
+    fn incomplete_tail_at_end_is_replaced() {
+        let cast = v2_cast(&[(0, 1, b"end\xf0\x9f")]);
+        let partial = convert_cast(cast, false).unwrap();
+        assert_eq!(payloads(&partial.content), ["end\u{fffd}"]);
+    }
+
+    #[test]
+    fn salvage_keeps_events_before_damage() {
+        let mut cast = v2_cast(&[(0, 1, b"one"), (1_000, 1, b"two")]);
+        let cut = cast.len() - 2;
+        cast.truncate(cut);
+        let offset = v2_cast(&[(0, 1, b"one")]).len() as u64;
+
+        assert!(convert_cast(cast.clone(), false).is_err());
+        let partial = convert_cast(cast, true).unwrap();
+        assert_eq!(payloads(&partial.content), ["one"]);
+        assert_eq!(partial.truncated.unwrap().offset, offset);
+    }
+
+    #[test]
+    fn salvage_stops_at_unknown_event_kind() {
+        let cast = v2_cast(&[(0, 1, b"one"), (1_000, 7, b"")]);
+        let partial = convert_cast(cast, true).unwrap();
+        assert_eq!(payloads(&partial.content), ["one"]);
+        assert!(partial.truncated.unwrap().reason.contains("unknown kind"));
+    }
+
+    /// A headerless v0 cast of `(elapsed seconds, data)` output events, after an 80x24 resize that
+    /// gives the converter its size.
+    fn v0_cast(events: &[(f32, &[u8])]) -> Vec<u8> {
+        let mut buf = 1_700_000_000_000u128.to_le_bytes().to_vec();
+        buf.extend_from_slice(&0f32.to_le_bytes());
+        buf.push(2);
+        buf.extend_from_slice(&24u16.to_le_bytes());
+        buf.extend_from_slice(&80u16.to_le_bytes());
+        for (elapsed, data) in events {
+            buf.extend_from_slice(&elapsed.to_le_bytes());
+            buf.push(1);
+            buf.extend_from_slice(unsigned_varint::encode::u32(data.len() as u32, &mut Default::default()));
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+
+    /// Converts `src` with a [`CastStream`], pushing the pieces between `cuts`; `content` holds all
+    /// output, taken or not.
+    fn streamed(src: &[u8], cuts: impl IntoIterator<Item = usize>, salvage: bool) -> CastPartial {
+        let mut stream = CastStream::new(salvage);
+        let mut out = Vec::new();
+        let mut from = 0;
+        for cut in cuts.into_iter().chain([src.len()]) {
+            stream.push(&src[from..cut]).unwrap();
+            out.extend(stream.take_output());
+            from = cut;
+        }
+        let mut partial = stream.finish().unwrap();
+        partial.content.insert_str(0, &String::from_utf8(out).unwrap());
+        partial
+    }
+
+    fn assert_same(streamed: &CastPartial, batch: &CastPartial) {
+        assert_eq!(streamed.content, batch.content);
+        assert_eq!(streamed.event_count, batch.event_count);
+        assert_eq!(streamed.repaired_events, batch.repaired_events);
+        assert_eq!(streamed.truncated.as_ref().map(|t| t.offset), batch.truncated.as_ref().map(|t| t.offset));
+    }
+
+    #[test]
+    fn stream_matches_batch_at_every_split() {
+        let cast = v2_cast(&[(0, 1, b"$ ls\r\n"), (1_000, 0, b"ls\r"), (2_000, 1, b"caf\xc3\xa9")]);
+        let batch = convert_cast(cast.clone(), false).unwrap();
+        for at in 0..=cast.len() {
+            assert_same(&streamed(&cast, [at], false), &batch);
+        }
+        assert_same(&streamed(&cast, 1..cast.len(), false), &batch);
+    }
+
+    #[test]
+    fn stream_matches_batch_when_salvaging() {
+        let mut cast = v2_cast(&[(0, 1, b"one"), (1_000, 1, b"two")]);
+        cast.truncate(cast.len() - 2);
+        let batch = convert_cast(cast.clone(), true).unwrap();
+        for at in 0..=cast.len() {
+            assert_same(&streamed(&cast, [at], true), &batch);
+        }
+    }
+
+    #[test]
+    fn v0_cast_is_streamed_incrementally() {
+        let line = b"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef\r\n";
+        let events = (0..2_000).map(|i| (i as f32 * 0.25, &line[..])).collect::<Vec<_>>();
+        let cast = v0_cast(&events);
+        assert!(cast.len() > HEADER_WINDOW);
+        let batch = convert_cast(cast.clone(), false).unwrap();
+
+        let mut stream = CastStream::new(false);
+        stream.push(&cast[..cast.len() - 1]).unwrap();
+        assert!(!stream.take_output().is_empty());
+
+        for at in (0..=cast.len()).step_by(997) {
+            assert_same(&streamed(&cast, [at], false), &batch);
+        }
+        assert_same(&streamed(&cast, 1..cast.len(), false), &batch);
+    }
+
+    #[test]
+    fn asciicast_stream_matches_batch() {
+        let cast = b"{\"version\": 2, \"width\": 80, \"height\": 24, \"timestamp\": 1700000000}\n[0.5, \"o\", \"hi\"]\n";
+        let batch = convert_cast(cast.to_vec(), false).unwrap();
+        for at in 0..=cast.len() {
+            assert_same(&streamed(cast, [at], false), &batch);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Reconstructs the command lines a user ran from the keys they typed, by replaying the line
+//! editing a shell does on its input. Shells that mark their prompts with OSC 133 and report their
+//! directory with OSC 7 also tell us where each command ran and how it exited.
+
+use serde::Serialize;
+
+use super::escape::{EscapeParser, Token};
+
+/// A line submitted with Enter, at `elapsed` microseconds into the recording.
+#[derive(Debug, Clone, Serialize)]
+pub struct Command {
+    pub elapsed: u64,
+    pub line: String,
+    /// The line was edited in a way the keys alone do not reveal, such as tab completion or
+    /// recalling history from before the recording, so it may differ from what actually ran.
+    pub approximate: bool,
+    /// From OSC 133 `D`, when the shell reports it.
+    pub exit_status: Option<i32>,
+    /// From the last OSC 7 before the command started.
+    pub cwd: Option<String>,
+    /// When the shell reported the command as finished.
+    pub finished: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+enum Escape {
+    #[default]
+    None,
+    Esc,
+    Csi(String),
+    Ss3,
+}
+
+/// The path of an OSC 7 `file://host/path` URL, percent-decoded.
+fn file_url_path(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// What the shell told us through OSC 133 about the command at its current prompt.
+#[derive(Debug, Default)]
+struct Prompt {
+    /// Number of commands recorded when the prompt ended and input began.
+    first_command: usize,
+    /// The command line as the shell echoed it, used when no typed line can be matched.
+    echoed: Option<String>,
+    /// Index of the command started at this prompt and not yet reported finished.
+    running: Option<usize>,
+}
+
+/// Follows a readline-style line editor through the input of a recording: printable keys,
+/// backspace, `Ctrl-U`/`Ctrl-W`/`Ctrl-K`, cursor movement and history recall with the arrow keys.
+/// Keys typed inside full-screen programs such as editors and pagers are not shell input and are
+/// skipped; the output is watched for the alternate screen to tell when that is the case, and
+/// for the OSC 133 and OSC 7 sequences shell integrations emit around each command.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    approximate: bool,
+    history: Vec<String>,
+    /// Index into `history` while recalling it with the arrow keys.
+    recall: Option<usize>,
+    escape: Escape,
+    fullscreen: bool,
+    output: EscapeParser,
+    prompt: Prompt,
+    cwd: Option<String>,
+    commands: Vec<Command>,
+}
+
+impl LineEditor {
+    pub fn input(&mut self, elapsed: u64, data: &[u8]) {
+        if self.fullscreen {
+            return;
+        }
+        for c in String::from_utf8_lossy(data).chars() {
+            self.key(elapsed, c);
+        }
+    }
+
+    pub fn output(&mut self, elapsed: u64, data: &[u8]) {
+        for c in String::from_utf8_lossy(data).chars() {
+            match self.output.next(c) {
+                Some(Token::Print(c)) => {
+                    if let Some(echoed) = &mut self.prompt.echoed {
+                        match c {
+                            '\x08' => {
+                                echoed.pop();
+                            }
+                            c if !c.is_control() => echoed.push(c),
+                            _ => {}
+                        }
+                    }
+                }
+                Some(Token::Fullscreen(fullscreen)) => self.set_fullscreen(fullscreen),
+                Some(Token::Osc(payload)) => self.osc(elapsed, &payload),
+                None => {}
+            }
+        }
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        if fullscreen != self.fullscreen {
+            self.fullscreen = fullscreen;
+            self.clear();
+        }
+    }
+
+    /// Handles OSC 7 (working directory) and OSC 133 (`A` prompt, `B` input, `C` command
+    /// started, `D` finished with an optional exit status).
+    fn osc(&mut self, elapsed: u64, payload: &str) {
+        let mut fields = payload.split(';');
+        match (fields.next(), fields.next()) {
+            (Some("7"), Some(url)) => self.cwd = file_url_path(url).or(self.cwd.take()),
+            (Some("133"), Some("A")) => self.prompt = Prompt::default(),
+            (Some("133"), Some("B")) => {
+                self.prompt.first_command = self.commands.len();
+                self.prompt.echoed = Some(String::new());
+            }
+            (Some("133"), Some("C")) => {
+                let echoed = self.prompt.echoed.take().unwrap_or_default();
+                // The line typed at this prompt, or failing that what the shell echoed.
+                let idx = if self.commands.len() > self.prompt.first_command {
+                    self.commands.len() - 1
+                } else {
+                    let line = echoed.trim().to_string();
+                    if line.is_empty() {
+                        return;
+                    }
+                    self.commands.push(Command {
+                        elapsed,
+                        line,
+                        approximate: true,
+                        exit_status: None,
+                        cwd: None,
+                        finished: None,
+                    });
+                    self.commands.len() - 1
+                };
+                self.commands[idx].cwd = self.cwd.clone();
+                self.prompt.running = Some(idx);
+            }
+            (Some("133"), Some("D")) => {
+                if let Some(idx) = self.prompt.running.take() {
+                    let command = &mut self.commands[idx];
+                    command.exit_status = fields.next().and_then(|s| s.trim().parse().ok());
+                    command.finished = Some(elapsed);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> Vec<Command> {
+        self.commands
+    }
+
+    fn key(&mut self, elapsed: u64, c: char) {
+        match std::mem::take(&mut self.escape) {
+            Escape::None => {}
+            Escape::Esc => {
+                match c {
+                    '[' => self.escape = Escape::Csi(String::new()),
+                    'O' => self.escape = Escape::Ss3,
+                    'b' => self.cursor = self.word_start(),
+                    'f' => self.cursor = self.word_end(),
+                    '\x7f' | '\x08' => self.delete_back(self.word_start()),
+                    _ => {}
+                }
+                return;
+            }
+            Escape::Csi(mut params) => {
+                if ('\x40'..='\x7e').contains(&c) {
+                    self.csi(&params, c);
+                } else {
+                    params.push(c);
+                    self.escape = Escape::Csi(params);
+                }
+                return;
+            }
+            Escape::Ss3 => {
+                self.csi("", c);
+                return;
+            }
+        }
+
+        match c {
+            '\x1b' => self.escape = Escape::Esc,
+            '\r' | '\n' => self.enter(elapsed),
+            '\x7f' | '\x08' => self.delete_back(self.cursor.saturating_sub(1)),
+            '\x15' => self.delete_back(0),
+            '\x17' => self.delete_back(self.shell_word_start()),
+            '\x0b' => self.line.truncate(self.cursor),
+            '\x04' if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            '\x01' => self.cursor = 0,
+            '\x05' => self.cursor = self.line.len(),
+            '\x02' => self.cursor = self.cursor.saturating_sub(1),
+            '\x06' => self.cursor = (self.cursor + 1).min(self.line.len()),
+            '\x10' => self.history_prev(),
+            '\x0e' => self.history_next(),
+            '\x03' => self.clear(),
+            // Completion and incremental search change the line based on what the shell shows.
+            '\t' | '\x12' => self.approximate = true,
+            c if c < ' ' => {}
+            c => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+        }
+    }
+
+    fn csi(&mut self, params: &str, c: char) {
+        match (c, params) {
+            ('A', _) => self.history_prev(),
+            ('B', _) => self.history_next(),
+            ('C', _) => self.cursor = (self.cursor + 1).min(self.line.len()),
+            ('D', _) => self.cursor = self.cursor.saturating_sub(1),
+            ('H', _) | ('~', "1" | "7") => self.cursor = 0,
+            ('F', _) | ('~', "4" | "8") => self.cursor = self.line.len(),
+            ('~', "3") if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            _ => {}
+        }
+    }
+
+    fn delete_back(&mut self, from: usize) {
+        self.line.drain(from..self.cursor);
+        self.cursor = from;
+    }
+
+    /// Start of the word before the cursor, as `Alt-B` sees words: runs of alphanumerics.
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.line[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.line[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.line.len() && !self.line[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.line.len() && self.line[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    /// Start of the word before the cursor, as `Ctrl-W` sees words: separated by whitespace.
+    fn shell_word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.line[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.line[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn set_line(&mut self, line: &str) {
+        self.line = line.chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    fn history_prev(&mut self) {
+        match self.recall.unwrap_or(self.history.len()) {
+            // Recalls history from before the recording, which we have not seen.
+            0 => self.approximate = true,
+            idx => {
+                self.recall = Some(idx - 1);
+                self.set_line(&self.history[idx - 1].clone());
+            }
+        }
+    }
+
+    fn history_next(&mut self) {
+        let Some(idx) = self.recall else {
+            return;
+        };
+        if idx + 1 < self.history.len() {
+            self.recall = Some(idx + 1);
+            self.set_line(&self.history[idx + 1].clone());
+        } else {
+            self.recall = None;
+            self.set_line("");
+        }
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.approximate = false;
+        self.recall = None;
+    }
+
+    fn enter(&mut self, elapsed: u64) {
+        let line = self.line.iter().collect::<String>().trim().to_string();
+        if !line.is_empty() {
+            if self.history.last() != Some(&line) {
+                self.history.push(line.clone());
+            }
+            self.commands.push(Command {
+                elapsed,
+                line,
+                approximate: self.approximate,
+                exit_status: None,
+                cwd: self.cwd.clone(),
+                finished: None,
+            });
+        }
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(keys: &str) -> Vec<Command> {
+        let mut editor = LineEditor::default();
+        editor.input(0, keys.as_bytes());
+        editor.finish()
+    }
+
+    fn lines(commands: &[Command]) -> Vec<&str> {
+        commands.iter().map(|c| c.line.as_str()).collect()
+    }
+
+    #[test]
+    fn replays_readline_editing() {
+        assert_eq!(lines(&typed("lss\x7f -l\r")), ["ls -l"]);
+        assert_eq!(lines(&typed("echo hi\x1b[D\x1b[Dx\r")), ["echo xhi"]);
+        assert_eq!(lines(&typed("cat foo\x01\x05 bar\r")), ["cat foo bar"]);
+        assert_eq!(lines(&typed("rm -rf /tmp/x\x17\x17ls\r")), ["rm ls"]);
+        assert_eq!(lines(&typed("oops\x15git status\r")), ["git status"]);
+        assert_eq!(lines(&typed("git log --stat\x1bb\x0b\r")), ["git log --"]);
+        assert_eq!(lines(&typed("abc\x1b[H\x1b[3~\r")), ["bc"]);
+        assert_eq!(lines(&typed("half\x03whole\r\r")), ["whole"]);
+    }
+
+    #[test]
+    fn recalls_history() {
+        let commands = typed("make\rmake test\r\x1b[A\x1b[A\r\x10\x10\x10\x0e\r");
+        assert_eq!(lines(&commands), ["make", "make test", "make", "make test"]);
+        assert!(commands.iter().all(|c| !c.approximate));
+    }
+
+    #[test]
+    fn unseen_history_and_completion_are_approximate() {
+        let commands = typed("\x1b[A\rgit che\tmain\rls\r");
+        assert_eq!(lines(&commands), ["git chemain", "ls"]);
+        assert_eq!(commands.iter().map(|c| c.approximate).collect::<Vec<_>>(), [true, false]);
+    }
+
+    #[test]
+    fn skips_keys_typed_in_fullscreen_programs() {
+        let mut editor = LineEditor::default();
+        editor.input(0, b"vim notes\r");
+        editor.output(1, b"\x1b[?1049h");
+        editor.input(2, b"ihello\x1b:wq\r");
+        // The mode switch may be split across output events.
+        editor.output(3, b"\x1b[?10");
+        editor.output(4, b"49l$ ");
+        editor.input(5, b"ls\r");
+        assert_eq!(lines(&editor.finish()), ["vim notes", "ls"]);
+    }
+
+    /// A bash session with an OSC 133/OSC 7 prompt integration, as `(elapsed, is_input, data)`.
+    const SESSION: &[(u64, bool, &str)] = &[
+        (0, false, "\x1b]133;A\x07\x1b]7;file://host/home/me\x07me@host:~$ \x1b]133;B\x07"),
+        (10, true, "ls"),
+        (11, false, "ls"),
+        (20, true, "\r"),
+        (21, false, "\r\n\x1b]133;C\x07notes.txt\r\n"),
+        (30, false, "\x1b]133;D;0\x07\x1b]133;A\x07me@host:~$ \x1b]133;B\x07"),
+        (40, true, "cd /tmp/my%20dir\r"),
+        (41, false, "cd /tmp/my%20dir\r\n\x1b]133;C\x07"),
+        // Terminated with ST and split across events.
+        (50, false, "\x1b]133;D;0\x1b\\\x1b]7;file://host/tmp/my%2520"),
+        (51, false, "dir\x1b\\\x1b]133;A\x07me@host:/tmp$ \x1b]133;B\x07"),
+        (60, true, "false\r"),
+        (61, false, "false\r\n\x1b]133;C\x07\x1b]133;D;1\x07\x1b]133;A\x07$ \x1b]133;B\x07"),
+        // Keys that never reached the recording; only the echo shows the line.
+        (71, false, "make chek\x08ck"),
+        (81, false, "\r\n\x1b]133;C\x07"),
+        (90, false, "\x1b]133;D;2\x07"),
+    ];
+
+    #[test]
+    fn follows_shell_integration() {
+        let mut editor = LineEditor::default();
+        for &(elapsed, input, data) in SESSION {
+            if input {
+                editor.input(elapsed, data.as_bytes());
+            } else {
+                editor.output(elapsed, data.as_bytes());
+            }
+        }
+        let commands = editor.finish();
+        assert_eq!(lines(&commands), ["ls", "cd /tmp/my%20dir", "false", "make check"]);
+        assert_eq!(
+            commands.iter().map(|c| c.exit_status).collect::<Vec<_>>(),
+            [Some(0), Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(
+            commands.iter().map(|c| c.cwd.as_deref()).collect::<Vec<_>>(),
+            [Some("/home/me"), Some("/home/me"), Some("/tmp/my%20dir"), Some("/tmp/my%20dir")]
+        );
+        assert_eq!(
+            commands.iter().map(|c| c.finished).collect::<Vec<_>>(),
+            [Some(30), Some(50), Some(61), Some(90)]
+        );
+        assert_eq!(commands.iter().map(|c| c.approximate).collect::<Vec<_>>(), [false, false, false, true]);
+    }
+
+    #[test]
+    fn decodes_file_urls() {
+        assert_eq!(file_url_path("file://host/a%20b/c").as_deref(), Some("/a b/c"));
+        assert_eq!(file_url_path("file:///plain").as_deref(), Some("/plain"));
+        assert_eq!(file_url_path("file://host/bad%2"), None);
+        assert_eq!(file_url_path("http://host/x"), None);
+    }
+}
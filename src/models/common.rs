@@ -1,36 +1,104 @@
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::operation::put_object::PutObjectError;
+use anyhow::Context;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use aws_sdk_s3::{Client, config::Builder as S3ConfBuilder, primitives::ByteStream};
-use axum::http::StatusCode;
+use axum::http::{StatusCode, header};
 use axum::response::IntoResponse;
 use axum::response::Response;
 use futures::future::try_join_all;
+use futures::try_join;
 use serde::Serialize;
 use sqlx::QueryBuilder;
-use sqlx::mysql::MySqlPoolOptions;
+use sqlx::mysql::{MySqlConnection, MySqlPoolOptions};
 use sqlx::{MySql, Pool, Row};
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
 use std::sync::Arc;
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
+use super::cast::Truncation;
+use super::commands::Command;
+use super::log::Diagnostic;
+use super::text::TextChunk;
+
+/// `name` parsed from the environment, or `default` when unset or invalid.
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
 pub type Heartbeats = Vec<(OffsetDateTime, OffsetDateTime)>;
 
+/// Heartbeats closer than this are folded into one interval.
+pub const HEARTBEAT_GAP: Duration = Duration::seconds(10);
+
+/// Sorts intervals and folds overlapping or nearby (within [`HEARTBEAT_GAP`]) ones together.
+pub fn merge_heartbeats(mut itvs: Heartbeats) -> Heartbeats {
+    itvs.sort();
+    itvs.into_iter().fold(Heartbeats::new(), |mut acc, (start, end)| {
+        match acc.last_mut() {
+            Some((_, last)) if start - *last <= HEARTBEAT_GAP => *last = end.max(*last),
+            _ => acc.push((start, end)),
+        }
+        acc
+    })
+}
+
 #[derive(Serialize)]
 pub struct UploadResp {
     pub ok: bool,
     pub url: String,
+    pub appended: bool,
+    pub skipped: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The upload repeats an earlier one and `url` is the log that was stored then.
+    pub duplicate: bool,
+}
+
+/// What makes an upload a repeat of an earlier one: the same `Idempotency-Key` header, or the
+/// same content as computed by [`content_hash`](super::log::content_hash).
+#[derive(Debug, Default, Clone)]
+pub struct UploadIdentity {
+    pub content_hash: Option<String>,
+    pub idempotency_key: Option<String>,
 }
 
+/// Outcome of [`MariaDB::insert`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Saved {
+    /// The upload was recorded, except for the named casts a concurrent append recorded first.
+    Stored(Vec<String>),
+    /// A concurrent upload of the same content created this log first; nothing was recorded.
+    Duplicate(Uuid),
+}
+
+/// Suffix of the object holding a cast's original recording, next to the converted one.
+pub const ORIGINAL_SUFFIX: &str = ".orig.zst";
+
 #[derive(Debug)]
 pub struct Cast {
     pub filename: String,
+    /// Empty for casts that were streamed to storage while they were converted.
     pub content: String,
+    /// The recording as uploaded, zstd-compressed, archived so it can be converted again later.
+    /// Empty when it was streamed to storage as well, `None` when there is none to keep.
+    pub original: Option<Vec<u8>>,
+    pub size_byte: u32,
     pub started_at: OffsetDateTime,
+    pub width: u16,
+    pub height: u16,
     pub duration: Duration,
     pub active_duration: Duration,
+    /// Active milliseconds per minute of the recording.
+    pub activity: Vec<u32>,
+    pub commands: Vec<Command>,
+    pub text: Vec<TextChunk>,
     pub event_count: u32,
+    pub repaired_events: u32,
+    pub truncated: Option<Truncation>,
+    pub checksum: Option<String>,
 }
 
 #[derive(Clone)]
@@ -59,12 +127,54 @@ pub struct CastMeta {
     pub bucket: String,
     pub path: String,
     pub size_byte: u32,
+    pub width: u16,
+    pub height: u16,
     pub duration: Duration,
     pub active_duration: Duration,
+    pub activity: Vec<u32>,
     pub event_count: u32,
+    pub repaired_events: u32,
+    pub truncated_at: Option<u64>,
+    pub truncated_reason: Option<String>,
     pub started_at: OffsetDateTime,
 }
 
+/// A staged part of a chunked upload, see [`MariaDB::stage_part`].
+#[derive(Debug, sqlx::FromRow)]
+pub struct UploadPart {
+    pub part: u32,
+    pub total: u32,
+    pub notes: String,
+    pub payload: String,
+}
+
+/// A queued upload, see [`MariaDB::enqueue_job`]. `state` is one of `queued`, `running`, `done`
+/// and `failed`; `result` holds the [`UploadResp`] of a finished job as JSON.
+#[derive(Debug, sqlx::FromRow)]
+pub struct JobMeta {
+    pub id: String,
+    pub log_uuid: String,
+    pub state: String,
+    pub notes: String,
+    pub strict: bool,
+    pub progress_done: u32,
+    pub progress_total: u32,
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// Where a cast and, for casts uploaded since originals are archived, its original are stored.
+#[derive(Debug, sqlx::FromRow)]
+pub struct CastSource {
+    pub id: u32,
+    pub bucket: String,
+    pub path: String,
+    pub original_path: Option<String>,
+}
+
 #[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct MarkMeta {
     pub id: u32,
@@ -72,6 +182,88 @@ pub struct MarkMeta {
     pub note: String,
 }
 
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CommandMeta {
+    pub id: u32,
+    pub second: f64,
+    pub line: String,
+    pub approximate: bool,
+    pub exit_status: Option<i32>,
+    pub cwd: Option<String>,
+    /// Seconds until the shell reported the command finished.
+    pub duration: Option<f64>,
+}
+
+/// A match of [`MariaDB::search`]: printed text when `kind` is `output`, a command line when it
+/// is `input`.
+#[derive(Debug, sqlx::FromRow)]
+pub struct SearchRow {
+    pub uuid: String,
+    pub note: String,
+    pub cast_id: u32,
+    pub path: String,
+    pub second: f64,
+    pub kind: String,
+    pub text: String,
+    pub score: f64,
+}
+
+impl CommandMeta {
+    pub fn failed(&self) -> bool {
+        self.exit_status.is_some_and(|status| status != 0)
+    }
+}
+
+/// Filenames of the casts stored under `uuid`.
+async fn cast_filenames(conn: &mut MySqlConnection, uuid: &str) -> anyhow::Result<HashSet<String>> {
+    let paths = sqlx::query_scalar!(r#"SELECT path AS `path!: String` FROM casts WHERE uuid=?"#, uuid)
+        .fetch_all(conn)
+        .await?;
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| path.rsplit('/').next().map(str::to_string))
+        .collect())
+}
+
+/// Inserts the commands of one or more casts, in batches that keep each statement well below the
+/// placeholder limit.
+async fn insert_commands(conn: &mut MySqlConnection, rows: &[(u32, &Command)]) -> anyhow::Result<()> {
+    for batch in rows.chunks(1000) {
+        let mut qb: QueryBuilder<MySql> =
+            QueryBuilder::new(r#"INSERT INTO commands (cast_id, second, line, approximate, exit_status, cwd, duration)"#);
+        qb.push_values(batch, |mut b, (cast_id, command)| {
+            b.push_bind(*cast_id);
+            b.push_bind(command.elapsed as f64 / 1_000_000.0);
+            b.push_bind(&command.line);
+            b.push_bind(command.approximate);
+            b.push_bind(command.exit_status);
+            b.push_bind(&command.cwd);
+            b.push_bind(
+                command
+                    .finished
+                    .map(|finished| finished.saturating_sub(command.elapsed) as f64 / 1_000_000.0),
+            );
+        });
+        qb.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Inserts the printed text of one or more casts; chunks are up to a few KiB, so batches are
+/// smaller than for commands to keep statements within the packet limit.
+async fn insert_text(conn: &mut MySqlConnection, rows: &[(u32, &TextChunk)]) -> anyhow::Result<()> {
+    for batch in rows.chunks(200) {
+        let mut qb: QueryBuilder<MySql> = QueryBuilder::new(r#"INSERT INTO cast_text (cast_id, second, body)"#);
+        qb.push_values(batch, |mut b, (cast_id, chunk)| {
+            b.push_bind(*cast_id);
+            b.push_bind(chunk.elapsed as f64 / 1_000_000.0);
+            b.push_bind(&chunk.text);
+        });
+        qb.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
 impl MariaDB {
     pub async fn new() -> anyhow::Result<Self> {
         let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -89,15 +281,80 @@ impl MariaDB {
         note: &String,
         heartbeats: &Heartbeats,
         casts: &[Cast],
-    ) -> anyhow::Result<()> {
+        identity: &UploadIdentity,
+    ) -> anyhow::Result<Saved> {
         let uuid_str = uuid.to_string();
 
         let mut tx = self.pool.begin().await?;
-        sqlx::query!(r#"INSERT INTO logs (uuid, note) VALUES (?, ?)"#, &uuid_str, note)
-            .execute(tx.deref_mut())
-            .await?;
+        let inserted = sqlx::query!(
+            r#"INSERT INTO logs (uuid, note, content_hash, idempotency_key) VALUES (?, ?, ?, ?)"#,
+            &uuid_str,
+            note,
+            &identity.content_hash,
+            &identity.idempotency_key
+        )
+        .execute(tx.deref_mut())
+        .await;
+        match inserted {
+            Ok(_) => {}
+            // Either the log exists and this upload appends to it, or another log already holds
+            // this content or idempotency key.
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                let existing =
+                    sqlx::query_scalar!(r#"SELECT note AS `note!: String` FROM logs WHERE uuid=? FOR UPDATE"#, &uuid_str)
+                        .fetch_optional(tx.deref_mut())
+                        .await?;
+                let Some(existing) = existing else {
+                    tx.rollback().await?;
+                    return match self.find_duplicate(identity).await? {
+                        Some(existing) => Ok(Saved::Duplicate(existing)),
+                        None => Err(sqlx::Error::Database(e).into()),
+                    };
+                };
+                // Retried or repeated appends carry the same note; keep one copy of it.
+                if !note.is_empty() && !format!("\n{existing}\n").contains(&format!("\n{note}\n")) {
+                    sqlx::query!(
+                        r#"UPDATE logs SET note = CONCAT_WS('\n', NULLIF(note, ''), ?) WHERE uuid=?"#,
+                        note,
+                        &uuid_str
+                    )
+                    .execute(tx.deref_mut())
+                    .await?;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Appends to the same log wait for each other on the logs row above, so a cast one of them
+        // recorded is seen here by the next rather than inserted twice.
+        let recorded = cast_filenames(tx.deref_mut(), &uuid_str).await?;
+        let (casts, skipped): (Vec<_>, Vec<_>) = casts.iter().partition(|cast| !recorded.contains(&cast.filename));
 
         if !heartbeats.is_empty() {
+            let existing: Heartbeats = sqlx::query!(
+                r#"
+                SELECT
+                    started_at AS `started_at!: OffsetDateTime`,
+                    ended_at   AS `ended_at!: OffsetDateTime`
+                FROM heartbeats
+                WHERE uuid=? AND session=0
+                FOR UPDATE
+                "#,
+                &uuid_str
+            )
+            .fetch_all(tx.deref_mut())
+            .await?
+            .into_iter()
+            .map(|row| (row.started_at, row.ended_at))
+            .collect();
+
+            if !existing.is_empty() {
+                sqlx::query!(r#"DELETE FROM heartbeats WHERE uuid=? AND session=0"#, &uuid_str)
+                    .execute(tx.deref_mut())
+                    .await?;
+            }
+            let heartbeats = merge_heartbeats(existing.into_iter().chain(heartbeats.iter().copied()).collect());
+
             let mut qb: QueryBuilder<MySql> =
                 QueryBuilder::new(r#"INSERT INTO heartbeats (uuid, session, started_at, ended_at)"#);
 
@@ -114,23 +371,331 @@ impl MariaDB {
             let bucket = std::env::var("S3_BUCKET").unwrap();
             let key = format!("{}/{}", std::env::var("S3_KEY_PREFIX").unwrap_or_default(), &uuid_str);
             let mut qb: QueryBuilder<MySql> = QueryBuilder::new(
-                r#"INSERT INTO casts (uuid, bucket, path, size_byte, duration, active_duration, event_count, started_at)"#,
+                r#"INSERT INTO casts (uuid, bucket, path, original_path, size_byte, width, height, duration, active_duration, activity, event_count, repaired_events, truncated_at, truncated_reason, sha256, started_at)"#,
             );
             qb.push_values(casts.iter(), |mut b, cast| {
                 b.push_bind(&uuid_str);
                 b.push_bind(&bucket);
                 b.push_bind(format!("{}/{}", key, cast.filename));
-                b.push_bind(cast.content.len() as u32);
+                b.push_bind(
+                    cast.original
+                        .is_some()
+                        .then(|| format!("{}/{}{}", key, cast.filename, ORIGINAL_SUFFIX)),
+                );
+                b.push_bind(cast.size_byte);
+                b.push_bind(cast.width);
+                b.push_bind(cast.height);
                 b.push_bind(cast.duration.whole_milliseconds() as u64);
                 b.push_bind(cast.active_duration.whole_milliseconds() as u64);
+                b.push_bind(serde_json::to_string(&cast.activity).unwrap_or_default());
                 b.push_bind(cast.event_count);
+                b.push_bind(cast.repaired_events);
+                b.push_bind(cast.truncated.as_ref().map(|t| t.offset));
+                b.push_bind(cast.truncated.as_ref().map(|t| t.reason.clone()));
+                b.push_bind(&cast.checksum);
                 b.push_bind(cast.started_at);
             });
             qb.build().execute(tx.deref_mut()).await?;
+
+            let ids: HashMap<String, u32> =
+                sqlx::query!(r#"SELECT path AS `path!: String`, id AS `id!: u32` FROM casts WHERE uuid=?"#, &uuid_str)
+                    .fetch_all(tx.deref_mut())
+                    .await?
+                    .into_iter()
+                    .map(|row| (row.path, row.id))
+                    .collect();
+            let stored = casts
+                .iter()
+                .filter_map(|cast| Some((*ids.get(&format!("{}/{}", key, cast.filename))?, cast)))
+                .collect::<Vec<_>>();
+            let commands = stored
+                .iter()
+                .flat_map(|&(id, cast)| cast.commands.iter().map(move |command| (id, command)))
+                .collect::<Vec<_>>();
+            insert_commands(tx.deref_mut(), &commands).await?;
+            let text = stored
+                .iter()
+                .flat_map(|&(id, cast)| cast.text.iter().map(move |chunk| (id, chunk)))
+                .collect::<Vec<_>>();
+            insert_text(tx.deref_mut(), &text).await?;
+        }
+
+        tx.commit().await?;
+        anyhow::Ok(Saved::Stored(skipped.into_iter().map(|cast| cast.filename.clone()).collect()))
+    }
+
+    /// The log an upload repeats: the one created with the same idempotency key, or failing that,
+    /// with the same content.
+    pub async fn find_duplicate(&self, identity: &UploadIdentity) -> anyhow::Result<Option<Uuid>> {
+        if identity.content_hash.is_none() && identity.idempotency_key.is_none() {
+            return Ok(None);
+        }
+        let row = sqlx::query_scalar!(
+            r#"
+            SELECT CAST(uuid AS CHAR) AS `uuid!: String` FROM logs
+            WHERE idempotency_key=? OR content_hash=?
+            ORDER BY idempotency_key <=> ? DESC
+            LIMIT 1
+            "#,
+            &identity.idempotency_key,
+            &identity.content_hash,
+            &identity.idempotency_key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|uuid| Uuid::parse_str(&uuid).context("invalid log uuid"))
+            .transpose()
+    }
+
+    /// Stores one part of a chunked upload until the remaining parts arrive, and returns the parts
+    /// staged so far. A re-sent part replaces the earlier copy; a part announcing a different total
+    /// than the parts already staged is not stored. Parts abandoned for more than a day are dropped.
+    pub async fn stage_part(
+        &self,
+        upload_id: &str,
+        part: u32,
+        total: u32,
+        notes: &str,
+        payload: &str,
+    ) -> anyhow::Result<Vec<UploadPart>> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(r#"DELETE FROM upload_parts WHERE received_at < NOW() - INTERVAL 1 DAY"#)
+            .execute(tx.deref_mut())
+            .await?;
+        let staged = sqlx::query_as!(
+            UploadPart,
+            r#"
+            SELECT
+                part    AS `part!: u32`,
+                total   AS `total!: u32`,
+                notes   AS `notes!: String`,
+                payload AS `payload!: String`
+            FROM upload_parts
+            WHERE upload_id=?
+            ORDER BY part
+            FOR UPDATE
+            "#,
+            upload_id
+        )
+        .fetch_all(tx.deref_mut())
+        .await?;
+        if staged.iter().any(|p| p.part != part && p.total != total) {
+            return Ok(staged);
         }
+        sqlx::query!(
+            r#"
+            INSERT INTO upload_parts (upload_id, part, total, notes, payload) VALUES (?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                total = VALUES(total), notes = VALUES(notes), payload = VALUES(payload), received_at = NOW()
+            "#,
+            upload_id,
+            part,
+            total,
+            notes,
+            payload
+        )
+        .execute(tx.deref_mut())
+        .await?;
+        let parts = sqlx::query_as!(
+            UploadPart,
+            r#"
+            SELECT
+                part    AS `part!: u32`,
+                total   AS `total!: u32`,
+                notes   AS `notes!: String`,
+                payload AS `payload!: String`
+            FROM upload_parts
+            WHERE upload_id=?
+            ORDER BY part
+            "#,
+            upload_id
+        )
+        .fetch_all(tx.deref_mut())
+        .await?;
+        tx.commit().await?;
+        Ok(parts)
+    }
 
+    pub async fn delete_parts(&self, upload_id: &str) -> anyhow::Result<()> {
+        sqlx::query!(r#"DELETE FROM upload_parts WHERE upload_id=?"#, upload_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Records a queued upload. Returns `false` without queueing anything if another job was
+    /// already queued with the same idempotency key.
+    pub async fn enqueue_job(
+        &self,
+        id: &Uuid,
+        log_uuid: &Uuid,
+        notes: &str,
+        strict: bool,
+        idempotency_key: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let inserted = sqlx::query!(
+            r#"INSERT INTO upload_jobs (id, log_uuid, notes, strict, idempotency_key) VALUES (?, ?, ?, ?, ?)"#,
+            id.to_string(),
+            log_uuid.to_string(),
+            notes,
+            strict,
+            idempotency_key
+        )
+        .execute(&self.pool)
+        .await;
+        match inserted {
+            Ok(_) => Ok(true),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() && idempotency_key.is_some() => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn query_job_by_key(&self, idempotency_key: &str) -> anyhow::Result<Option<JobMeta>> {
+        let job = sqlx::query_as!(
+            JobMeta,
+            r#"
+            SELECT
+                CAST(id AS CHAR)       AS `id!: String`,
+                CAST(log_uuid AS CHAR) AS `log_uuid!: String`,
+                state                  AS `state!: String`,
+                notes                  AS `notes!: String`,
+                strict                 AS `strict!: bool`,
+                progress_done          AS `progress_done!: u32`,
+                progress_total         AS `progress_total!: u32`,
+                result                 AS `result?: String`,
+                error                  AS `error?: String`,
+                idempotency_key        AS `idempotency_key?: String`,
+                created_at             AS `created_at!: OffsetDateTime`,
+                updated_at             AS `updated_at!: OffsetDateTime`
+            FROM upload_jobs
+            WHERE idempotency_key=?
+            "#,
+            idempotency_key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(job)
+    }
+
+    /// Marks the oldest queued job as running under a lease held by `worker` and returns it. Workers
+    /// skip rows another worker is claiming, so each job is picked up once.
+    pub async fn claim_job(&self, worker: &Uuid) -> anyhow::Result<Option<JobMeta>> {
+        let mut tx = self.pool.begin().await?;
+        let job = sqlx::query_as!(
+            JobMeta,
+            r#"
+            SELECT
+                CAST(id AS CHAR)       AS `id!: String`,
+                CAST(log_uuid AS CHAR) AS `log_uuid!: String`,
+                state                  AS `state!: String`,
+                notes                  AS `notes!: String`,
+                strict                 AS `strict!: bool`,
+                progress_done          AS `progress_done!: u32`,
+                progress_total         AS `progress_total!: u32`,
+                result                 AS `result?: String`,
+                error                  AS `error?: String`,
+                idempotency_key        AS `idempotency_key?: String`,
+                created_at             AS `created_at!: OffsetDateTime`,
+                updated_at             AS `updated_at!: OffsetDateTime`
+            FROM upload_jobs
+            WHERE state='queued'
+            ORDER BY created_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .fetch_optional(tx.deref_mut())
+        .await?;
+        if let Some(job) = &job {
+            sqlx::query!(
+                r#"UPDATE upload_jobs SET state='running', locked_by=?, locked_at=NOW() WHERE id=?"#,
+                worker.to_string(),
+                &job.id
+            )
+            .execute(tx.deref_mut())
+            .await?;
+        }
         tx.commit().await?;
-        anyhow::Ok(())
+        Ok(job)
+    }
+
+    /// Puts running jobs whose lease was not renewed for `lease_secs` back in the queue; their
+    /// worker stopped or lost the database.
+    pub async fn requeue_expired_jobs(&self, lease_secs: u64) -> anyhow::Result<u64> {
+        let requeued = sqlx::query!(
+            r#"
+            UPDATE upload_jobs SET state='queued', progress_done=0, locked_by=NULL, locked_at=NULL
+            WHERE state='running' AND locked_at < NOW() - INTERVAL ? SECOND
+            "#,
+            lease_secs
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(requeued.rows_affected())
+    }
+
+    /// Records progress and renews the lease of `worker` on the job.
+    pub async fn update_job_progress(&self, id: &str, worker: &Uuid, done: u32, total: u32) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"UPDATE upload_jobs SET progress_done=?, progress_total=?, locked_at=NOW() WHERE id=? AND locked_by=?"#,
+            done,
+            total,
+            id,
+            worker.to_string()
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Records the outcome of a job: the response JSON on success, the error message on failure.
+    /// Returns `false` if `worker` lost its lease and the job was requeued.
+    pub async fn finish_job(&self, id: &str, worker: &Uuid, outcome: Result<String, String>) -> anyhow::Result<bool> {
+        let (state, result, error) = match outcome {
+            Ok(result) => ("done", Some(result), None),
+            Err(error) => ("failed", None, Some(error)),
+        };
+        let finished = sqlx::query!(
+            r#"
+            UPDATE upload_jobs SET state=?, result=?, error=?, locked_by=NULL, locked_at=NULL
+            WHERE id=? AND state='running' AND locked_by=?
+            "#,
+            state,
+            result,
+            error,
+            id,
+            worker.to_string()
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(finished.rows_affected() > 0)
+    }
+
+    pub async fn query_job(&self, id: &Uuid) -> anyhow::Result<Option<JobMeta>> {
+        let job = sqlx::query_as!(
+            JobMeta,
+            r#"
+            SELECT
+                CAST(id AS CHAR)       AS `id!: String`,
+                CAST(log_uuid AS CHAR) AS `log_uuid!: String`,
+                state                  AS `state!: String`,
+                notes                  AS `notes!: String`,
+                strict                 AS `strict!: bool`,
+                progress_done          AS `progress_done!: u32`,
+                progress_total         AS `progress_total!: u32`,
+                result                 AS `result?: String`,
+                error                  AS `error?: String`,
+                idempotency_key        AS `idempotency_key?: String`,
+                created_at             AS `created_at!: OffsetDateTime`,
+                updated_at             AS `updated_at!: OffsetDateTime`
+            FROM upload_jobs
+            WHERE id=?
+            "#,
+            id.to_string()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(job)
     }
 
     pub async fn query_logs(&self) -> anyhow::Result<Vec<LogMeta>> {
@@ -171,6 +736,11 @@ impl MariaDB {
         Ok(row)
     }
 
+    /// Filenames of the casts already stored under `uuid`, used to skip re-uploaded casts.
+    pub async fn query_cast_filenames(&self, uuid: &Uuid) -> anyhow::Result<HashSet<String>> {
+        cast_filenames(self.pool.acquire().await?.deref_mut(), &uuid.to_string()).await
+    }
+
     pub async fn query_heartbeats(&self, uuid: &Uuid) -> anyhow::Result<Vec<HeartbeatMeta>> {
         let rows = sqlx::query_as!(
             HeartbeatMeta,
@@ -197,9 +767,15 @@ impl MariaDB {
             pub bucket: String,
             pub path: String,
             pub size_byte: u32,
+            pub width: u16,
+            pub height: u16,
             pub duration: u64,
             pub active_duration: u64,
+            pub activity: Option<String>,
             pub event_count: u32,
+            pub repaired_events: u32,
+            pub truncated_at: Option<u64>,
+            pub truncated_reason: Option<String>,
             pub started_at: OffsetDateTime,
         }
 
@@ -211,9 +787,15 @@ impl MariaDB {
                 bucket            AS `bucket!: String`,
                 path              AS `path!: String`,
                 size_byte         AS `size_byte!: u32`,
+                width             AS `width!: u16`,
+                height            AS `height!: u16`,
                 duration          AS `duration!: u64`,
                 active_duration   AS `active_duration!: u64`,
+                activity          AS `activity?: String`,
                 event_count       AS `event_count!: u32`,
+                repaired_events   AS `repaired_events!: u32`,
+                truncated_at      AS `truncated_at?: u64`,
+                truncated_reason  AS `truncated_reason?: String`,
                 started_at        AS `started_at!: OffsetDateTime`
             FROM casts
             WHERE uuid=?
@@ -231,9 +813,18 @@ impl MariaDB {
                 bucket: row.bucket,
                 path: row.path,
                 size_byte: row.size_byte,
+                width: row.width,
+                height: row.height,
                 duration: Duration::milliseconds(row.duration as i64),
                 active_duration: Duration::milliseconds(row.active_duration as i64),
+                activity: row
+                    .activity
+                    .and_then(|a| serde_json::from_str(&a).ok())
+                    .unwrap_or_default(),
                 event_count: row.event_count,
+                repaired_events: row.repaired_events,
+                truncated_at: row.truncated_at,
+                truncated_reason: row.truncated_reason,
                 started_at: row.started_at,
             })
             .collect::<Vec<CastMeta>>();
@@ -241,6 +832,146 @@ impl MariaDB {
         Ok(casts)
     }
 
+    /// Every cast, or those of one log, in upload order.
+    pub async fn query_cast_sources(&self, uuid: Option<&Uuid>) -> anyhow::Result<Vec<CastSource>> {
+        let uuid = uuid.map(Uuid::to_string);
+        let rows = sqlx::query_as!(
+            CastSource,
+            r#"
+            SELECT
+                id            AS `id!: u32`,
+                bucket        AS `bucket!: String`,
+                path          AS `path!: String`,
+                original_path AS `original_path?: String`
+            FROM casts
+            WHERE ? IS NULL OR uuid=?
+            ORDER BY id
+            "#,
+            &uuid,
+            &uuid
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Replaces the metadata, commands and text of a cast that was converted again.
+    pub async fn update_cast(&self, id: u32, cast: &Cast) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"
+            UPDATE casts SET
+                size_byte=?, width=?, height=?, duration=?, active_duration=?, activity=?, event_count=?,
+                repaired_events=?, truncated_at=?, truncated_reason=?, started_at=?
+            WHERE id=?
+            "#,
+            cast.size_byte,
+            cast.width,
+            cast.height,
+            cast.duration.whole_milliseconds() as u64,
+            cast.active_duration.whole_milliseconds() as u64,
+            serde_json::to_string(&cast.activity)?,
+            cast.event_count,
+            cast.repaired_events,
+            cast.truncated.as_ref().map(|t| t.offset),
+            cast.truncated.as_ref().map(|t| &t.reason),
+            cast.started_at,
+            id
+        )
+        .execute(tx.deref_mut())
+        .await?;
+        sqlx::query!(r#"DELETE FROM commands WHERE cast_id=?"#, id)
+            .execute(tx.deref_mut())
+            .await?;
+        let commands = cast.commands.iter().map(|command| (id, command)).collect::<Vec<_>>();
+        insert_commands(tx.deref_mut(), &commands).await?;
+        sqlx::query!(r#"DELETE FROM cast_text WHERE cast_id=?"#, id)
+            .execute(tx.deref_mut())
+            .await?;
+        let text = cast.text.iter().map(|chunk| (id, chunk)).collect::<Vec<_>>();
+        insert_text(tx.deref_mut(), &text).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn query_commands(&self, cast_id: u32) -> anyhow::Result<Vec<CommandMeta>> {
+        let rows = sqlx::query_as!(
+            CommandMeta,
+            r#"
+            SELECT
+                id          AS `id!: u32`,
+                second      AS `second!: f64`,
+                line        AS `line!: String`,
+                approximate AS `approximate!: bool`,
+                exit_status AS `exit_status?: i32`,
+                cwd         AS `cwd?: String`,
+                duration    AS `duration?: f64`
+            FROM commands
+            WHERE cast_id=?
+            ORDER BY second, id
+            "#,
+            cast_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Full-text search over the printed text and command lines of every cast, best matches
+    /// first. Hidden logs are left out unless `include_hidden` is set.
+    pub async fn search(
+        &self,
+        query: &str,
+        include_hidden: bool,
+        limit: u32,
+        offset: u32,
+    ) -> anyhow::Result<Vec<SearchRow>> {
+        let rows = sqlx::query_as!(
+            SearchRow,
+            r#"
+            SELECT
+                uuid    AS `uuid!: String`,
+                note    AS `note!: String`,
+                cast_id AS `cast_id!: u32`,
+                path    AS `path!: String`,
+                second  AS `second!: f64`,
+                kind    AS `kind!: String`,
+                text    AS `text!: String`,
+                score   AS `score!: f64`
+            FROM (
+                SELECT CAST(l.uuid AS CHAR) AS uuid, l.note, c.id AS cast_id, c.path, t.second,
+                       'output' AS kind, t.body AS text,
+                       MATCH(t.body) AGAINST (? IN NATURAL LANGUAGE MODE) AS score
+                FROM cast_text t
+                JOIN casts c ON c.id = t.cast_id
+                JOIN logs l ON l.uuid = c.uuid
+                WHERE MATCH(t.body) AGAINST (? IN NATURAL LANGUAGE MODE) AND (l.visible OR ?)
+                UNION ALL
+                SELECT CAST(l.uuid AS CHAR) AS uuid, l.note, c.id AS cast_id, c.path, m.second,
+                       'input' AS kind, m.line AS text,
+                       MATCH(m.line) AGAINST (? IN NATURAL LANGUAGE MODE) AS score
+                FROM commands m
+                JOIN casts c ON c.id = m.cast_id
+                JOIN logs l ON l.uuid = c.uuid
+                WHERE MATCH(m.line) AGAINST (? IN NATURAL LANGUAGE MODE) AND (l.visible OR ?)
+            ) hits
+            ORDER BY score DESC, uuid, cast_id, second
+            LIMIT ? OFFSET ?
+            "#,
+            query,
+            query,
+            include_hidden,
+            query,
+            query,
+            include_hidden,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
     pub async fn query_marks(&self, id: u32) -> anyhow::Result<Vec<MarkMeta>> {
         let rows = sqlx::query_as!(
             MarkMeta,
@@ -356,20 +1087,212 @@ impl MinIO {
             let body = c.content.clone().into_bytes();
             async move { self.upload(&key, body).await }
         });
-        try_join_all(tasks).await.map(|_| ())
+        let originals = casts.iter().filter(|c| c.original.as_ref().is_some_and(|o| !o.is_empty())).map(|c| {
+            let key = format!("{}/{}/{}{}", prefix, &uuid, c.filename, ORIGINAL_SUFFIX);
+            let body = c.original.clone().unwrap_or_default();
+            async move { self.upload(&key, body).await }
+        });
+        try_join!(try_join_all(tasks), try_join_all(originals)).map(|_| ())
+    }
+
+    pub async fn get_object_bytes(&self, bucket: &str, key: &str) -> anyhow::Result<Vec<u8>> {
+        let body = self.get_object_stream(bucket, key).await?;
+        Ok(body.collect().await?.into_bytes().to_vec())
+    }
+
+    /// Overwrites an object, e.g. a cast that was converted again.
+    pub async fn put_object(&self, bucket: &str, key: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        self.client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(body.into())
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    fn job_key(id: &Uuid) -> String {
+        let prefix = std::env::var("S3_KEY_PREFIX").unwrap_or_default();
+        format!("{}/jobs/{}.log", prefix, id)
+    }
+
+    /// Keeps the payload of a queued upload until a worker gets to it.
+    pub async fn upload_job_payload(&self, id: &Uuid, payload: String) -> Result<(), SdkError<PutObjectError>> {
+        self.upload(&Self::job_key(id), payload.into_bytes()).await
+    }
+
+    pub async fn job_payload(&self, id: &Uuid) -> anyhow::Result<String> {
+        let bytes = self.get_object_bytes(&self.bucket, &Self::job_key(id)).await?;
+        String::from_utf8(bytes).context("job payload is not valid UTF-8")
+    }
+
+    pub async fn delete_job_payload(&self, id: &Uuid) -> anyhow::Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(Self::job_key(id))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Removes everything stored for a log, e.g. after it turned out to repeat an earlier upload.
+    pub async fn delete_log(&self, uuid: &Uuid) -> anyhow::Result<()> {
+        let prefix = std::env::var("S3_KEY_PREFIX").unwrap_or_default();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(format!("{}/{}/", prefix, uuid))
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            for object in page?.contents() {
+                if let Some(key) = object.key() {
+                    self.client.delete_object().bucket(&self.bucket).key(key).send().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts writing a cast that is converted while its upload is still arriving.
+    pub fn stream_cast(&self, uuid: &Uuid, filename: &str) -> MultipartUpload {
+        let prefix = std::env::var("S3_KEY_PREFIX").unwrap_or_default();
+        MultipartUpload {
+            client: self.client.clone(),
+            bucket: self.bucket.clone(),
+            key: format!("{}/{}/{}", prefix, uuid, filename),
+            upload_id: None,
+            parts: Vec::new(),
+            buf: Vec::new(),
+            size: 0,
+        }
     }
 
     pub async fn upload_heartbeats(
         &self,
         uuid: &Uuid,
+        name: &str,
         hb_raw: &str,
     ) -> Result<(), SdkError<aws_sdk_s3::operation::put_object::PutObjectError>> {
         let prefix = std::env::var("S3_KEY_PREFIX").unwrap_or_default();
-        self.upload(
-            &format!("{}/{}/heartbeats.log", prefix, uuid),
-            hb_raw.as_bytes().to_vec(),
-        )
-        .await
+        self.upload(&format!("{}/{}/{}", prefix, uuid, name), hb_raw.as_bytes().to_vec())
+            .await
+    }
+}
+
+/// Size of the parts a streamed object is written in; S3 needs at least 5 MiB for all but the last.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// An object written piece by piece with an S3 multipart upload. The upload is only started once
+/// more than one part has been written, so small objects end up as a single `PutObject`.
+pub struct MultipartUpload {
+    client: Arc<Client>,
+    bucket: String,
+    key: String,
+    upload_id: Option<String>,
+    parts: Vec<CompletedPart>,
+    buf: Vec<u8>,
+    size: u64,
+}
+
+impl MultipartUpload {
+    pub async fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.buf.extend_from_slice(bytes);
+        self.size += bytes.len() as u64;
+        while self.buf.len() >= PART_SIZE {
+            let rest = self.buf.split_off(PART_SIZE);
+            let part = std::mem::replace(&mut self.buf, rest);
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn upload_part(&mut self, part: Vec<u8>) -> anyhow::Result<()> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let out = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .send()
+                    .await
+                    .with_context(|| format!("failed to start multipart upload of {}", self.key))?;
+                let upload_id = out.upload_id.context("multipart upload without an id")?;
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+        let part_number = self.parts.len() as i32 + 1;
+        let out = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(part.into())
+            .send()
+            .await
+            .with_context(|| format!("failed to upload part {part_number} of {}", self.key))?;
+        self.parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(out.e_tag)
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Writes what is left and finishes the object, returning its size in bytes.
+    pub async fn complete(mut self) -> anyhow::Result<u64> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .body(std::mem::take(&mut self.buf).into())
+                .send()
+                .await
+                .with_context(|| format!("failed to upload {}", self.key))?;
+            return Ok(self.size);
+        };
+        if !self.buf.is_empty() {
+            let last = std::mem::take(&mut self.buf);
+            self.upload_part(last).await?;
+        }
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(std::mem::take(&mut self.parts)))
+                    .build(),
+            )
+            .send()
+            .await
+            .with_context(|| format!("failed to complete multipart upload of {}", self.key))?;
+        Ok(self.size)
+    }
+
+    /// Drops the parts written so far.
+    pub async fn abort(self) {
+        if let Some(upload_id) = self.upload_id {
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
     }
 }
 
@@ -382,18 +1305,45 @@ pub enum AppError {
     DbCtx(#[from] anyhow::Error),
 
     #[error("storage: {0}")]
-    Storage(#[from] SdkError<PutObjectError>),
+    Storage(Box<SdkError<PutObjectError>>),
+
+    #[error("storage: {0:#}")]
+    StreamStorage(anyhow::Error),
 
     #[error("log {0} not found")]
     LogNotFound(Uuid),
+
+    #[error("job {0} not found")]
+    JobNotFound(Uuid),
+
+    #[error("a valid admin token is required")]
+    Forbidden,
+
+    #[error("busy converting other uploads, retry in {0}s")]
+    Busy(u64),
+}
+
+impl From<SdkError<PutObjectError>> for AppError {
+    fn from(e: SdkError<PutObjectError>) -> Self {
+        AppError::Storage(Box::new(e))
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         match &self {
             AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
-            AppError::LogNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
-            AppError::DbCtx(_) | AppError::Storage(_) => {
+            AppError::LogNotFound(_) | AppError::JobNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            AppError::Forbidden => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            AppError::Busy(retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                self.to_string(),
+            )
+                .into_response(),
+            AppError::DbCtx(_) | AppError::Storage(_) | AppError::StreamStorage(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
             }
         }
@@ -0,0 +1,108 @@
+//! Separates the characters a terminal prints from the escape sequences in its output. Shared by
+//! the readers of recorded output, which each care about only a few sequences.
+
+/// What a character of output amounts to, once the sequence it ends is known.
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    /// A character outside any escape sequence, control characters included.
+    Print(char),
+    /// A full-screen program switched to (`true`) or back from (`false`) the alternate screen.
+    Fullscreen(bool),
+    /// The payload of an OSC sequence, without its terminator.
+    Osc(String),
+}
+
+/// Private modes that switch to and from the alternate screen used by full-screen programs.
+const FULLSCREEN_MODES: [&str; 3] = ["?1049", "?1047", "?47"];
+
+/// OSC payloads we care about are short; longer ones (e.g. clipboard writes) are cut off here.
+const MAX_OSC: usize = 4096;
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Ground,
+    Esc,
+    /// `ESC (` and friends, which take one more character.
+    Charset,
+    Csi(String),
+    /// OSC, DCS, SOS, PM and APC strings, ended by BEL or `ESC \`. Only OSC payloads are kept.
+    Str(Option<String>),
+    /// Saw ESC inside a string, which is the start of its `ESC \` terminator.
+    StrEsc(Option<String>),
+}
+
+/// Scans output one character at a time; sequences may span output events.
+#[derive(Debug, Default)]
+pub struct EscapeParser {
+    state: State,
+}
+
+impl EscapeParser {
+    pub fn next(&mut self, c: char) -> Option<Token> {
+        let (state, token) = match (std::mem::take(&mut self.state), c) {
+            (State::Ground, '\x1b') => (State::Esc, None),
+            (State::Ground, c) => (State::Ground, Some(Token::Print(c))),
+            (State::Esc, '[') => (State::Csi(String::new()), None),
+            (State::Esc, ']') => (State::Str(Some(String::new())), None),
+            (State::Esc, 'P' | 'X' | '^' | '_') => (State::Str(None), None),
+            (State::Esc, '(' | ')' | '*' | '+' | '#' | '%') => (State::Charset, None),
+            (State::Esc | State::Charset, _) => (State::Ground, None),
+            (State::Csi(params), '\x40'..='\x7e') => {
+                let switch = matches!(c, 'h' | 'l') && FULLSCREEN_MODES.contains(&params.as_str());
+                (State::Ground, switch.then_some(Token::Fullscreen(c == 'h')))
+            }
+            (State::Csi(mut params), c) => {
+                params.push(c);
+                (State::Csi(params), None)
+            }
+            (State::Str(payload), '\x07') | (State::StrEsc(payload), '\\') => (State::Ground, payload.map(Token::Osc)),
+            (State::Str(payload), '\x1b') => (State::StrEsc(payload), None),
+            (State::Str(mut payload), c) => {
+                if let Some(payload) = &mut payload
+                    && payload.len() < MAX_OSC
+                {
+                    payload.push(c);
+                }
+                (State::Str(payload), None)
+            }
+            (State::StrEsc(_), _) => (State::Ground, None),
+        };
+        self.state = state;
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(output: &str) -> Vec<Token> {
+        let mut parser = EscapeParser::default();
+        output.chars().filter_map(|c| parser.next(c)).collect()
+    }
+
+    #[test]
+    fn drops_sequences_between_printed_characters() {
+        let printed = tokens("a\x1b[1;31mb\x1b(Bc\x1bPq#0\x1b\\d\x1b=e\r\n");
+        assert_eq!(printed, "abcde\r\n".chars().map(Token::Print).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reports_alternate_screen_switches() {
+        assert_eq!(
+            tokens("\x1b[?1049hx\x1b[?1049l\x1b[?47h\x1b[?25l"),
+            [Token::Fullscreen(true), Token::Print('x'), Token::Fullscreen(false), Token::Fullscreen(true)]
+        );
+    }
+
+    #[test]
+    fn keeps_osc_payloads() {
+        assert_eq!(
+            tokens("\x1b]0;title\x07\x1b]133;D;1\x1b\\"),
+            [Token::Osc("0;title".to_string()), Token::Osc("133;D;1".to_string())]
+        );
+        let long = format!("\x1b]52;c;{}\x07", "A".repeat(10_000));
+        assert!(matches!(&tokens(&long)[..], [Token::Osc(payload)] if payload.len() == MAX_OSC));
+    }
+}
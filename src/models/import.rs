@@ -0,0 +1,529 @@
+//! Readers for recordings made by other tools, normalised into a [`Recording`] so they are stored
+//! and replayed exactly like casts from the workspace recorder.
+
+use anyhow::{Context, bail};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use time::OffsetDateTime;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+
+use super::cast::{CAST_MAGIC, CastHeader, Event, Recording};
+
+/// Recording formats accepted as a single file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Our own recorder, any header version.
+    Binary,
+    Asciicast,
+    Ttyrec,
+}
+
+/// Guesses the format of a recording from its content. Versioned binary casts and asciicasts are
+/// recognised by their first bytes; ttyrec has no magic, so a file only counts as ttyrec when its
+/// frame headers carry plausible timestamps and account for every byte. Anything else is assumed
+/// to be a headerless v0 binary cast.
+pub fn detect(src: &[u8]) -> Format {
+    if src.starts_with(CAST_MAGIC) {
+        Format::Binary
+    } else if is_asciicast(src) {
+        Format::Asciicast
+    } else if ttyrec_frames(src).is_some() {
+        Format::Ttyrec
+    } else {
+        Format::Binary
+    }
+}
+
+fn now_millis() -> u128 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as u128
+}
+
+/// The asciicast version announced by the first line of `src`, if it is a v2 or v3 header.
+fn asciicast_version(src: &[u8]) -> Option<u64> {
+    let first = src.split(|&b| b == b'\n').next()?;
+    let header = serde_json::from_slice::<Value>(first).ok()?;
+    header.get("version")?.as_u64().filter(|v| matches!(v, 2 | 3))
+}
+
+fn is_asciicast(src: &[u8]) -> bool {
+    asciicast_version(src).is_some()
+}
+
+#[derive(Deserialize)]
+struct AsciicastTerm {
+    cols: u16,
+    rows: u16,
+    #[serde(rename = "type")]
+    term_type: Option<String>,
+}
+
+/// The header fields we keep; v2 puts the size at the top level, v3 under `term`.
+#[derive(Deserialize)]
+struct AsciicastHeader {
+    width: Option<u16>,
+    height: Option<u16>,
+    term: Option<AsciicastTerm>,
+    timestamp: Option<i64>,
+    command: Option<String>,
+    #[serde(default)]
+    env: HashMap<String, Option<String>>,
+}
+
+/// Reads an asciicast v2 or v3 file. v2 event times are absolute and v3 times are intervals; both
+/// become microseconds since the start. Event codes other than `i`, `o` and `r` are kept as they are.
+pub fn read_asciicast(src: &[u8]) -> anyhow::Result<Recording> {
+    let version = asciicast_version(src).context("not an asciicast v2/v3 file")?;
+    let text = std::str::from_utf8(src).context("asciicast is not valid UTF-8")?;
+    let mut lines = text.lines().enumerate();
+
+    let (_, first) = lines.next().context("empty asciicast")?;
+    let meta = serde_json::from_str::<AsciicastHeader>(first)
+        .with_context(|| format!("invalid asciicast v{version} header"))?;
+    let size = match (&meta.term, meta.width, meta.height) {
+        (Some(term), _, _) => Some((term.cols, term.rows)),
+        (None, Some(cols), Some(rows)) => Some((cols, rows)),
+        _ => None,
+    }
+    .filter(|(cols, rows)| *cols > 0 && *rows > 0);
+    let mut env = meta.env;
+    let mut env_var = |key: &str| env.remove(key).flatten().filter(|v| !v.is_empty());
+    let ts = meta
+        .timestamp
+        .map(|secs| secs.max(0) as u128 * 1000)
+        .unwrap_or_else(now_millis);
+    let header = CastHeader {
+        ts,
+        size,
+        term: meta.term.and_then(|t| t.term_type).or_else(|| env_var("TERM")),
+        shell: env_var("SHELL"),
+        hostname: env_var("HOSTNAME"),
+        command: meta.command.filter(|c| !c.is_empty()),
+    };
+
+    let mut events = Vec::new();
+    let mut prev = 0u64;
+    for (idx, line) in lines {
+        let line = line.trim();
+        if line.is_empty() || (version == 3 && line.starts_with('#')) {
+            continue;
+        }
+        let lineno = idx + 1;
+        let (time, code, data) = serde_json::from_str::<(f64, String, String)>(line)
+            .with_context(|| format!("invalid asciicast event on line {lineno}"))?;
+        if !time.is_finite() || time < 0.0 {
+            bail!("invalid event time {time} on line {lineno}");
+        }
+        let micros = (time * 1_000_000.0).round() as u64;
+        let elapsed = if version == 2 {
+            if micros < prev {
+                bail!("event on line {lineno} is earlier than the one before it");
+            }
+            micros
+        } else {
+            prev + micros
+        };
+        prev = elapsed;
+
+        events.push(match code.as_str() {
+            "i" => Event::Input {
+                elapsed,
+                data: data.into_bytes(),
+            },
+            "o" => Event::Output {
+                elapsed,
+                data: data.into_bytes(),
+            },
+            "r" => {
+                let (cols, rows) = data
+                    .split_once('x')
+                    .and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)))
+                    .with_context(|| format!("invalid resize {data:?} on line {lineno}"))?;
+                Event::Resize { elapsed, cols, rows }
+            }
+            _ => Event::Other { elapsed, code, data },
+        });
+    }
+
+    Ok(Recording {
+        header,
+        events,
+        truncated: None,
+    })
+}
+
+/// Whether a file starting with `prefix` could be detected as something other than a binary cast.
+/// Never false for a file [`detect`] would take as an import, so a cast can be decoded from its
+/// first bytes when this says no.
+pub fn may_be_import(prefix: &[u8]) -> bool {
+    let first = prefix.iter().find(|b| !b.is_ascii_whitespace());
+    matches!(first, None | Some(b'{')) || ttyrec_walk(prefix, false).is_some()
+}
+
+fn ttyrec_frames(src: &[u8]) -> Option<Vec<(u64, &[u8])>> {
+    ttyrec_walk(src, true)
+}
+
+/// ttyrec frames as (microseconds since the epoch, output), or `None` if `src` is not a well-formed
+/// ttyrec file, or with `complete` unset, the start of one. Each frame is a 12-byte little-endian
+/// header `sec, usec, len` followed by `len` bytes.
+fn ttyrec_walk(src: &[u8], complete: bool) -> Option<Vec<(u64, &[u8])>> {
+    // 2000-01-01. Together with the `usec` bound and the exact length check this keeps headerless
+    // v0 casts from passing as ttyrec.
+    const EARLIEST: u64 = 946_684_800;
+    let latest = OffsetDateTime::now_utc().unix_timestamp() as u64 + 86_400;
+    let word = |b: &[u8]| u32::from_le_bytes(b.try_into().unwrap()) as u64;
+
+    let mut frames = Vec::new();
+    let mut rest = src;
+    while !rest.is_empty() {
+        let Some((header, body)) = rest.split_at_checked(12) else {
+            return (!complete).then_some(frames);
+        };
+        let (sec, usec, len) = (word(&header[0..4]), word(&header[4..8]), word(&header[8..12]) as usize);
+        if !(EARLIEST..=latest).contains(&sec) || usec >= 1_000_000 {
+            return None;
+        }
+        if body.len() < len {
+            return (!complete).then_some(frames);
+        }
+        frames.push((sec * 1_000_000 + usec, &body[..len]));
+        rest = &body[len..];
+    }
+    (!complete || !frames.is_empty()).then_some(frames)
+}
+
+/// Reads a ttyrec file. ttyrec only records output, with absolute timestamps; the first frame
+/// marks the start of the recording.
+pub fn read_ttyrec(src: &[u8]) -> anyhow::Result<Recording> {
+    let frames = ttyrec_frames(src).context("not a ttyrec file")?;
+    let start = frames[0].0;
+    let events = frames
+        .into_iter()
+        .map(|(micros, data)| Event::Output {
+            elapsed: micros.saturating_sub(start),
+            data: data.to_vec(),
+        })
+        .collect();
+    Ok(Recording {
+        header: CastHeader {
+            ts: (start / 1000) as u128,
+            ..Default::default()
+        },
+        events,
+        truncated: None,
+    })
+}
+
+static SCRIPT_VAR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(\w+)="([^"]*)""#).unwrap());
+
+/// `script` writes its start time as e.g. `2024-03-01 09:15:02+01:00`.
+const SCRIPT_TIME: &[FormatItem<'_>] = format_description!(
+    "[year]-[month]-[day] [hour]:[minute]:[second][offset_hour sign:mandatory]:[offset_minute]"
+);
+
+fn parse_script_time(text: &str) -> Option<u128> {
+    let dt = OffsetDateTime::parse(text.trim(), SCRIPT_TIME).ok()?;
+    Some((dt.unix_timestamp_nanos() / 1_000_000) as u128)
+}
+
+/// Applies one `script` metadata variable, from either the typescript header or an `H` timing line.
+fn apply_script_var(header: &mut CastHeader, key: &str, value: &str) {
+    let value = value.trim();
+    if value.is_empty() {
+        return;
+    }
+    let (cols, rows) = header.size.unwrap_or_default();
+    match key {
+        "TERM" => header.term = Some(value.to_string()),
+        "SHELL" => header.shell = Some(value.to_string()),
+        "COMMAND" => header.command = Some(value.to_string()),
+        "COLUMNS" => header.size = value.parse().ok().map(|cols| (cols, rows)).or(header.size),
+        "LINES" => header.size = value.parse().ok().map(|rows| (cols, rows)).or(header.size),
+        "START_TIME" => header.ts = parse_script_time(value).unwrap_or(header.ts),
+        _ => {}
+    }
+}
+
+/// Reads a `script -t` session: the typescript holds the raw terminal stream and the timing file
+/// says how many bytes were written after each delay. Both the classic `delay bytes` timing format
+/// and the multi-stream format of `script -T` (`O`/`I` data, `S` signals, `H` header lines) are
+/// understood. The typescript's own `Script started on` line provides the start time and terminal
+/// details when present.
+pub fn read_typescript(typescript: &[u8], timing: &[u8]) -> anyhow::Result<Recording> {
+    let timing = std::str::from_utf8(timing).context("timing file is not valid UTF-8")?;
+    let mut header = CastHeader {
+        ts: now_millis(),
+        ..Default::default()
+    };
+
+    let mut data = typescript;
+    if typescript.starts_with(b"Script started on ") {
+        let end = typescript.iter().position(|&b| b == b'\n').map_or(typescript.len(), |i| i + 1);
+        let first = String::from_utf8_lossy(&typescript[..end]);
+        let started = first["Script started on ".len()..].split(" [").next().unwrap_or_default();
+        header.ts = parse_script_time(started).unwrap_or(header.ts);
+        for caps in SCRIPT_VAR_RE.captures_iter(&first) {
+            apply_script_var(&mut header, &caps[1], &caps[2]);
+        }
+        data = &typescript[end..];
+    }
+    header.size = header.size.filter(|(cols, rows)| *cols > 0 && *rows > 0);
+
+    let mut events = Vec::new();
+    let mut elapsed = 0u64;
+    let mut pos = 0usize;
+    for (idx, line) in timing.lines().enumerate() {
+        let lineno = idx + 1;
+        let mut fields = line.split_whitespace();
+        let Some(first) = fields.next() else {
+            continue;
+        };
+        let (code, delay) = match first.parse::<f64>() {
+            Ok(delay) => ("O", delay),
+            Err(_) => (
+                first,
+                fields
+                    .next()
+                    .and_then(|d| d.parse::<f64>().ok())
+                    .with_context(|| format!("missing delay on timing line {lineno}"))?,
+            ),
+        };
+        if !delay.is_finite() || delay < 0.0 {
+            bail!("invalid delay {delay} on timing line {lineno}");
+        }
+        elapsed += (delay * 1_000_000.0).round() as u64;
+
+        match code {
+            "O" | "I" => {
+                let len = fields
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .with_context(|| format!("missing byte count on timing line {lineno}"))?;
+                let chunk = pos
+                    .checked_add(len)
+                    .and_then(|end| data.get(pos..end))
+                    .with_context(|| format!("timing line {lineno} reads past the end of the typescript"))?
+                    .to_vec();
+                pos += len;
+                events.push(match code {
+                    "O" => Event::Output { elapsed, data: chunk },
+                    _ => Event::Input { elapsed, data: chunk },
+                });
+            }
+            "S" => {
+                let vars = fields
+                    .skip(1)
+                    .filter_map(|f| f.split_once('='))
+                    .filter_map(|(k, v)| Some((k, v.parse::<u16>().ok()?)))
+                    .collect::<HashMap<_, _>>();
+                if let (Some(&cols), Some(&rows)) = (vars.get("COLS"), vars.get("ROWS")) {
+                    events.push(Event::Resize { elapsed, cols, rows });
+                }
+            }
+            "H" => {
+                let key = fields.next().unwrap_or_default();
+                let value = fields.collect::<Vec<_>>().join(" ");
+                apply_script_var(&mut header, key, &value);
+            }
+            _ => bail!("unknown timing entry {code:?} on line {lineno}"),
+        }
+    }
+
+    Ok(Recording {
+        header,
+        events,
+        truncated: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(recording: &Recording) -> Vec<(u64, String)> {
+        recording
+            .events
+            .iter()
+            .map(|event| match event {
+                Event::Input { elapsed, data } => (*elapsed, format!("i {}", String::from_utf8_lossy(data))),
+                Event::Output { elapsed, data } => (*elapsed, format!("o {}", String::from_utf8_lossy(data))),
+                Event::Resize { elapsed, cols, rows } => (*elapsed, format!("r {cols}x{rows}")),
+                Event::Other { elapsed, code, data } => (*elapsed, format!("{code} {data}")),
+            })
+            .collect()
+    }
+
+    const V2: &str = concat!(
+        r#"{"version": 2, "width": 100, "height": 30, "timestamp": 1700000000, "env": {"SHELL": "/bin/zsh", "TERM": "xterm"}}"#,
+        "\n",
+        r#"[0.5, "o", "hello"]"#,
+        "\n",
+        r#"[1.25, "i", "x"]"#,
+        "\n",
+        r#"[2.0, "r", "120x40"]"#,
+        "\n",
+        r#"[2.5, "m", "chapter"]"#,
+        "\n",
+    );
+
+    const V3: &str = concat!(
+        r#"{"version": 3, "term": {"cols": 90, "rows": 20, "type": "xterm-256color"}, "command": "htop"}"#,
+        "\n",
+        "# a comment\n",
+        r#"[0.5, "o", "a"]"#,
+        "\n",
+        r#"[0.25, "o", "b"]"#,
+        "\n",
+        "\n",
+        r#"[1.0, "x", "0"]"#,
+        "\n",
+    );
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(detect(V2.as_bytes()), Format::Asciicast);
+        assert_eq!(detect(V3.as_bytes()), Format::Asciicast);
+        assert_eq!(detect(b"PTYR\x02rest"), Format::Binary);
+        assert_eq!(detect(br#"{"version": 1}"#), Format::Binary);
+        assert_eq!(detect(&1_700_000_000_000u128.to_le_bytes()), Format::Binary);
+    }
+
+    #[test]
+    fn reads_asciicast_v2() {
+        let recording = read_asciicast(V2.as_bytes()).unwrap();
+        assert_eq!(recording.header.size, Some((100, 30)));
+        assert_eq!(recording.header.ts, 1_700_000_000_000);
+        assert_eq!(recording.header.shell.as_deref(), Some("/bin/zsh"));
+        assert_eq!(recording.header.term.as_deref(), Some("xterm"));
+        assert_eq!(
+            summary(&recording),
+            [
+                (500_000, "o hello".to_string()),
+                (1_250_000, "i x".to_string()),
+                (2_000_000, "r 120x40".to_string()),
+                (2_500_000, "m chapter".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_asciicast_v3_intervals() {
+        let recording = read_asciicast(V3.as_bytes()).unwrap();
+        assert_eq!(recording.header.size, Some((90, 20)));
+        assert_eq!(recording.header.term.as_deref(), Some("xterm-256color"));
+        assert_eq!(recording.header.command.as_deref(), Some("htop"));
+        assert_eq!(
+            summary(&recording),
+            [
+                (500_000, "o a".to_string()),
+                (750_000, "o b".to_string()),
+                (1_750_000, "x 0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_asciicast_v2_going_back_in_time() {
+        let src = "{\"version\": 2, \"width\": 80, \"height\": 24}\n[1.0, \"o\", \"a\"]\n[0.5, \"o\", \"b\"]\n";
+        let err = read_asciicast(src.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 3"));
+    }
+
+    /// Output frames written as a ttyrec file starting at 1700000000.25 s.
+    fn ttyrec(frames: &[(u64, &[u8])]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (elapsed, data) in frames {
+            let micros = 1_700_000_000_250_000 + elapsed;
+            buf.extend_from_slice(&((micros / 1_000_000) as u32).to_le_bytes());
+            buf.extend_from_slice(&((micros % 1_000_000) as u32).to_le_bytes());
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+
+    /// Output frames written as a `script -t` typescript and classic timing file.
+    fn typescript(frames: &[(u64, &[u8])]) -> (Vec<u8>, String) {
+        let mut typescript = b"Script started on 2023-11-14 22:13:20+00:00 [TERM=\"xterm\" COLUMNS=\"100\" LINES=\"30\"]\n".to_vec();
+        let mut timing = String::new();
+        let mut prev = 0;
+        for (elapsed, data) in frames {
+            typescript.extend_from_slice(data);
+            timing += &format!("{:.6} {}\n", (elapsed - prev) as f64 / 1_000_000.0, data.len());
+            prev = *elapsed;
+        }
+        (typescript, timing)
+    }
+
+    const FRAMES: [(u64, &[u8]); 3] = [(0, b"$ ls\r\n"), (250_000, b"a  b\r\n"), (1_500_000, b"$ ")];
+
+    #[test]
+    fn ttyrec_round_trips() {
+        let src = ttyrec(&FRAMES);
+        assert_eq!(detect(&src), Format::Ttyrec);
+        let recording = read_ttyrec(&src).unwrap();
+        assert_eq!(recording.header.ts, 1_700_000_000_250);
+        assert_eq!(
+            summary(&recording),
+            FRAMES.map(|(elapsed, data)| (elapsed, format!("o {}", String::from_utf8_lossy(data))))
+        );
+    }
+
+    #[test]
+    fn truncated_ttyrec_is_not_detected() {
+        let mut src = ttyrec(&FRAMES);
+        src.pop();
+        assert_eq!(detect(&src), Format::Binary);
+    }
+
+    #[test]
+    fn typescript_round_trips() {
+        let (src, timing) = typescript(&FRAMES);
+        let recording = read_typescript(&src, timing.as_bytes()).unwrap();
+        assert_eq!(recording.header.ts, 1_700_000_000_000);
+        assert_eq!(recording.header.size, Some((100, 30)));
+        assert_eq!(recording.header.term.as_deref(), Some("xterm"));
+        assert_eq!(
+            summary(&recording),
+            FRAMES.map(|(elapsed, data)| (elapsed, format!("o {}", String::from_utf8_lossy(data))))
+        );
+    }
+
+    #[test]
+    fn typescript_and_ttyrec_convert_alike() {
+        let (src, timing) = typescript(&FRAMES);
+        let from_typescript = crate::models::cast::convert_typescript(&src, timing.as_bytes()).unwrap();
+        let from_ttyrec = crate::models::cast::convert_cast(ttyrec(&FRAMES), false).unwrap();
+        let events = |content: &str| content.lines().skip(1).map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(events(&from_typescript.content), events(&from_ttyrec.content));
+    }
+
+    #[test]
+    fn reads_advanced_timing_format() {
+        let timing = "H 0.000000 START_TIME 2023-11-14 22:13:20+00:00\nO 0.5 3\nI 0.25 1\nS 0.25 SIGWINCH ROWS=40 COLS=120\nO 0.0 2\n";
+        let recording = read_typescript(b"abcxde", timing.as_bytes()).unwrap();
+        assert_eq!(recording.header.ts, 1_700_000_000_000);
+        assert_eq!(
+            summary(&recording),
+            [
+                (500_000, "o abc".to_string()),
+                (750_000, "i x".to_string()),
+                (1_000_000, "r 120x40".to_string()),
+                (1_000_000, "o de".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn timing_past_the_end_is_rejected() {
+        let huge = format!("0.1 {}\n", usize::MAX);
+        for timing in ["0.1 3\n0.1 10\n", huge.as_str()] {
+            let err = read_typescript(b"abcdef", timing.as_bytes()).unwrap_err();
+            assert!(err.to_string().contains("past the end"), "{err}");
+        }
+    }
+}
@@ -1,3 +1,10 @@
 pub mod common;
 pub use common::*;
-pub mod log;
+pub mod cast;
+pub mod commands;
+pub mod escape;
+pub mod import;
+pub use pty_replay_web::log;
+pub mod pool;
+pub mod text;
+pub use pool::WorkPool;
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{AppError, env_or};
+
+/// Seconds a client is told to wait when [`WorkPool::admit`] turns it away.
+const RETRY_AFTER: u64 = 5;
+
+/// Runs conversions on blocking threads, `CONVERT_WORKERS` at a time, with at most `CONVERT_QUEUE`
+/// uploads admitted.
+#[derive(Clone)]
+pub struct WorkPool {
+    workers: Arc<Semaphore>,
+    queue: Arc<Semaphore>,
+}
+
+pub struct Ticket(#[allow(dead_code, reason = "held for its Drop")] OwnedSemaphorePermit);
+
+impl WorkPool {
+    pub fn new() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let workers = env_or("CONVERT_WORKERS", cpus).max(1);
+        let queue = env_or("CONVERT_QUEUE", 32).max(1);
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            queue: Arc::new(Semaphore::new(queue)),
+        }
+    }
+
+    /// Fails with [`AppError::Busy`] when the queue is full.
+    pub fn admit(&self) -> Result<Ticket, AppError> {
+        self.queue
+            .clone()
+            .try_acquire_owned()
+            .map(Ticket)
+            .map_err(|_| AppError::Busy(RETRY_AFTER))
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let worker = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| AppError::DbCtx(e.into()))?;
+        tokio::task::spawn_blocking(move || {
+            let _worker = worker;
+            job()
+        })
+        .await
+        .map_err(|e| AppError::DbCtx(anyhow::anyhow!("conversion job failed: {e}")))
+    }
+}
@@ -0,0 +1,181 @@
+//! Extracts the plain text a recording printed, for full-text search. Escape sequences are
+//! dropped, carriage returns overwrite the line like a terminal would, and output of full-screen
+//! programs is left out since it is drawn with cursor movement rather than written as lines.
+
+use super::escape::{EscapeParser, Token};
+
+/// Lines printed from `elapsed` microseconds into the recording, joined with newlines.
+#[derive(Debug, Clone)]
+pub struct TextChunk {
+    pub elapsed: u64,
+    pub text: String,
+}
+
+/// A chunk holds at most this many bytes, a longer line being split over several...
+const CHUNK_BYTES: usize = 4096;
+/// ...and is closed early when its next line was printed this long after it started, so a hit is
+/// never far from the time it is reported at.
+const CHUNK_SPAN: u64 = 10_000_000;
+/// Text beyond this much per recording is not indexed.
+const MAX_TEXT_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Default)]
+pub struct OutputText {
+    escape: EscapeParser,
+    fullscreen: bool,
+    line: String,
+    /// When the first character of `line` was printed.
+    line_start: u64,
+    /// A carriage return was printed; the next character starts the line over.
+    carriage: bool,
+    chunk: Option<TextChunk>,
+    chunks: Vec<TextChunk>,
+    size: usize,
+}
+
+impl OutputText {
+    pub fn output(&mut self, elapsed: u64, data: &[u8]) {
+        for c in String::from_utf8_lossy(data).chars() {
+            self.char(elapsed, c);
+        }
+    }
+
+    fn char(&mut self, elapsed: u64, c: char) {
+        match self.escape.next(c) {
+            Some(Token::Print(c)) => self.print(elapsed, c),
+            Some(Token::Fullscreen(fullscreen)) => {
+                self.end_line();
+                self.fullscreen = fullscreen;
+            }
+            Some(Token::Osc(_)) | None => {}
+        }
+    }
+
+    fn print(&mut self, elapsed: u64, c: char) {
+        if self.fullscreen {
+            return;
+        }
+        match c {
+            '\n' => self.end_line(),
+            '\r' => self.carriage = true,
+            '\x08' => {
+                self.line.pop();
+            }
+            '\t' => self.push(elapsed, ' '),
+            c if c.is_control() => {}
+            c => self.push(elapsed, c),
+        }
+    }
+
+    fn push(&mut self, elapsed: u64, c: char) {
+        if std::mem::take(&mut self.carriage) {
+            self.line.clear();
+        }
+        if self.line.is_empty() {
+            self.line_start = elapsed;
+        }
+        self.line.push(c);
+    }
+
+    fn end_line(&mut self) {
+        self.carriage = false;
+        let line = std::mem::take(&mut self.line);
+        let mut line = line.trim_end();
+        if line.trim_start().is_empty() {
+            return;
+        }
+        // A line longer than a chunk is spread over several.
+        while !line.is_empty() {
+            let mut end = line.len().min(CHUNK_BYTES);
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            let (head, rest) = line.split_at(end);
+            self.append(head);
+            line = rest;
+        }
+    }
+
+    fn append(&mut self, line: &str) {
+        if self.size >= MAX_TEXT_BYTES {
+            return;
+        }
+        self.size += line.len() + 1;
+        if let Some(chunk) = &self.chunk
+            && (chunk.text.len() + 1 + line.len() > CHUNK_BYTES || self.line_start.saturating_sub(chunk.elapsed) > CHUNK_SPAN)
+        {
+            self.chunks.extend(self.chunk.take());
+        }
+        match &mut self.chunk {
+            Some(chunk) => {
+                chunk.text.push('\n');
+                chunk.text.push_str(line);
+            }
+            None => {
+                self.chunk = Some(TextChunk {
+                    elapsed: self.line_start,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<TextChunk> {
+        self.end_line();
+        self.chunks.extend(self.chunk.take());
+        self.chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(events: &[(u64, &str)]) -> Vec<(u64, String)> {
+        let mut text = OutputText::default();
+        for (elapsed, data) in events {
+            text.output(*elapsed, data.as_bytes());
+        }
+        text.finish().into_iter().map(|chunk| (chunk.elapsed, chunk.text)).collect()
+    }
+
+    #[test]
+    fn keeps_printed_lines_without_sequences() {
+        let chunks = text(&[(5, "\x1b[32m$\x1b[0m ls\r\n\x1b]0;title\x07a\tb  \r\n\r\n"), (6, "tail")]);
+        assert_eq!(chunks, [(5, "$ ls\na b\ntail".to_string())]);
+    }
+
+    #[test]
+    fn carriage_return_and_backspace_overwrite() {
+        let chunks = text(&[(0, "10%\r50%\r100% done\r\n"), (1, "typo\x08\x08po\r\n")]);
+        assert_eq!(chunks, [(0, "100% done\ntypo".to_string())]);
+    }
+
+    #[test]
+    fn skips_fullscreen_output() {
+        let chunks = text(&[(0, "vim x\r\n\x1b[?1049h"), (1, "~\r\n~\r\n"), (2, "\x1b[?1049l$ done\r\n")]);
+        assert_eq!(chunks, [(0, "vim x\n$ done".to_string())]);
+    }
+
+    #[test]
+    fn splits_chunks_by_time_and_size() {
+        let late = CHUNK_SPAN + 1;
+        let chunks = text(&[(0, "first\r\n"), (1, "second\r\n"), (late + 1, "third\r\n")]);
+        assert_eq!(chunks, [(0, "first\nsecond".to_string()), (late + 1, "third".to_string())]);
+
+        let line = "x".repeat(1000) + "\r\n";
+        let chunks = text(&[(0, &line.repeat(10))]);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|(_, text)| text.len() <= CHUNK_BYTES));
+    }
+
+    #[test]
+    fn splits_lines_longer_than_a_chunk() {
+        let line = "é".repeat(40 * 1024);
+        let chunks = text(&[(0, "before\r\n"), (1, &line), (2, "\r\nafter")]);
+        assert!(chunks.len() > 20);
+        assert!(chunks.iter().all(|(_, text)| text.len() <= CHUNK_BYTES));
+        let joined: String = chunks.iter().map(|(_, text)| text.replace('\n', "")).collect();
+        assert_eq!(joined, format!("before{line}after"));
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Context;
+use axum::Json;
+use axum::extract::State;
+use axum::http::{HeaderMap, header};
+use axum::response::IntoResponse;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::models::cast::convert_cast;
+use crate::models::{AppError, Cast, CastSource};
+use crate::upload::salvage_enabled;
+
+/// Casts converted at the same time; the work pool bounds the CPU side, this bounds downloads.
+const CONCURRENCY: usize = 4;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReprocessMeta {
+    /// Only reprocess the casts of this log.
+    uuid: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReprocessFailure {
+    cast_id: u32,
+    path: String,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReprocessReport {
+    ok: bool,
+    reprocessed: usize,
+    /// Casts without an archived original, which cannot be converted again: those uploaded before
+    /// originals were archived and those imported from a typescript and its timing file.
+    skipped: usize,
+    failed: Vec<ReprocessFailure>,
+}
+
+/// Converts one archived original again, replacing the converted object and its metadata.
+async fn reprocess_cast(app: &AppState, source: &CastSource, salvage: bool) -> anyhow::Result<()> {
+    let original_path = source.original_path.as_deref().context("no archived original")?;
+    let compressed = app.minio.get_object_bytes(&source.bucket, original_path).await?;
+    let partial = app
+        .pool
+        .run(move || {
+            let raw = zstd::decode_all(&compressed[..]).context("failed to decompress original")?;
+            convert_cast(raw, salvage)
+        })
+        .await??;
+    let started_at = OffsetDateTime::from_unix_timestamp(partial.timestamp).context("invalid timestamp")?;
+    let cast = Cast {
+        filename: Path::new(&source.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        size_byte: partial.content.len() as u32,
+        content: partial.content,
+        original: None,
+        started_at,
+        width: partial.width,
+        height: partial.height,
+        duration: partial.duration,
+        active_duration: partial.active_duration,
+        activity: partial.activity,
+        commands: partial.commands,
+        text: partial.text,
+        event_count: partial.event_count,
+        repaired_events: partial.repaired_events,
+        truncated: partial.truncated,
+        checksum: None,
+    };
+    app.minio
+        .put_object(&source.bucket, &source.path, cast.content.as_bytes().to_vec())
+        .await?;
+    app.db.update_cast(source.id, &cast).await
+}
+
+/// Runs the current converter over the archived originals of every cast, or of one log's casts,
+/// so converter fixes reach recordings uploaded before them. Failures are reported per cast and
+/// leave that cast as it was.
+pub async fn reprocess(app: &AppState, uuid: Option<Uuid>) -> Result<ReprocessReport, AppError> {
+    let sources = app.db.query_cast_sources(uuid.as_ref()).await?;
+    let (archived, unarchived): (Vec<_>, Vec<_>) = sources.into_iter().partition(|s| s.original_path.is_some());
+    let salvage = salvage_enabled();
+    let attempted = archived.len();
+
+    let failed = futures::stream::iter(archived)
+        .map(|source| async move {
+            let outcome = reprocess_cast(app, &source, salvage).await;
+            (source, outcome)
+        })
+        .buffer_unordered(CONCURRENCY)
+        .filter_map(|(source, outcome)| async move {
+            outcome.err().map(|e| ReprocessFailure {
+                cast_id: source.id,
+                path: source.path,
+                error: format!("{e:#}"),
+            })
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(ReprocessReport {
+        ok: failed.is_empty(),
+        reprocessed: attempted - failed.len(),
+        skipped: unarchived.len(),
+        failed,
+    })
+}
+
+/// Checks the `Authorization: Bearer` header against `ADMIN_TOKEN`. Admin endpoints are disabled
+/// while no token is configured.
+fn authorize(headers: &HeaderMap) -> Result<(), AppError> {
+    let token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match (token, given) {
+        (Some(token), Some(given)) if token == given => Ok(()),
+        _ => Err(AppError::Forbidden),
+    }
+}
+
+/// Admin endpoint for [`reprocess`]; the body may name the log to limit it to.
+pub async fn admin_reprocess(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(meta): Json<ReprocessMeta>,
+) -> Result<impl IntoResponse, AppError> {
+    authorize(&headers)?;
+    Ok(Json(reprocess(&app, meta.uuid).await?))
+}
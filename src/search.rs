@@ -0,0 +1,195 @@
+use crate::AppState;
+use crate::models::{AppError, SearchRow};
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 200;
+/// Snippets longer than this are cut around the first matching word.
+const SNIPPET_CHARS: usize = 160;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchQuery {
+    q: String,
+    /// Include logs hidden from the list.
+    all: bool,
+    limit: Option<u32>,
+    offset: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    uuid: String,
+    note: String,
+    cast_id: u32,
+    filename: String,
+    second: f64,
+    /// `output` for printed text, `input` for a command line.
+    kind: String,
+    snippet: String,
+    /// Relevance as ranked by the full-text index; only comparable within one search.
+    score: f64,
+    /// The view page, seeked to the hit.
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResp {
+    ok: bool,
+    query: String,
+    hits: Vec<SearchHit>,
+    /// Offset of the next page, when there may be one.
+    next_offset: Option<u32>,
+}
+
+impl SearchHit {
+    pub fn time_mmss(&self) -> String {
+        let s = self.second as u64;
+        format!("{}m{:02}s", s / 60, s % 60)
+    }
+}
+
+/// The words a full-text query matches on, lowercased.
+fn terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Character offset of the first case-insensitive match of the lowercase `term` in `line`.
+/// Lowercasing may change the length of the text, so the match is looked for in `line` itself.
+fn find_lowercase(line: &str, term: &str) -> Option<usize> {
+    line.char_indices().enumerate().find_map(|(n, (i, _))| {
+        let mut rest = line[i..].chars().flat_map(char::to_lowercase);
+        term.chars().all(|t| rest.next() == Some(t)).then_some(n)
+    })
+}
+
+/// The first line of `text` containing one of `terms`, cut to [`SNIPPET_CHARS`] around it.
+fn snippet(text: &str, terms: &[String]) -> String {
+    let found = text.lines().map(str::trim).find_map(|line| {
+        let at = terms.iter().find_map(|t| find_lowercase(line, t))?;
+        Some((line, at))
+    });
+    let (line, at_char) = found.unwrap_or((text.lines().next().unwrap_or_default().trim(), 0));
+    let start = at_char.saturating_sub(SNIPPET_CHARS / 4);
+    let total = line.chars().count();
+    let mut snippet = line.chars().skip(start).take(SNIPPET_CHARS).collect::<String>();
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if start + SNIPPET_CHARS < total {
+        snippet.push('…');
+    }
+    snippet
+}
+
+async fn run(app: &AppState, query: &SearchQuery) -> Result<SearchResp, AppError> {
+    let q = query.q.trim();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    if q.is_empty() {
+        return Ok(SearchResp {
+            ok: true,
+            query: String::new(),
+            hits: Vec::new(),
+            next_offset: None,
+        });
+    }
+    let rows = app.db.search(q, query.all, limit, query.offset).await?;
+    let next_offset = (rows.len() as u32 == limit).then_some(query.offset + limit);
+    let terms = terms(q);
+    let hits = rows
+        .into_iter()
+        .map(|row: SearchRow| SearchHit {
+            url: format!("/view/{}?cast={}&t={:.1}", row.uuid, row.cast_id, row.second),
+            filename: Path::new(&row.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            snippet: snippet(&row.text, &terms),
+            uuid: row.uuid,
+            note: row.note,
+            cast_id: row.cast_id,
+            second: row.second,
+            kind: row.kind,
+            score: row.score,
+        })
+        .collect();
+    Ok(SearchResp {
+        ok: true,
+        query: q.to_string(),
+        hits,
+        next_offset,
+    })
+}
+
+/// Matches of `q` in the printed output and typed commands of every cast, best first.
+pub async fn search_api(
+    State(app): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(run(&app, &query).await?))
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "search.html")]
+pub struct SearchTemplate {
+    query: String,
+    all: bool,
+    /// Hits grouped by log, in the order of each log's best hit.
+    logs: Vec<(String, String, Vec<SearchHit>)>,
+    next_offset: Option<u32>,
+}
+
+pub async fn search(State(app): State<AppState>, Query(query): Query<SearchQuery>) -> Result<SearchTemplate, AppError> {
+    let resp = run(&app, &query).await?;
+    let mut logs = Vec::<(String, String, Vec<SearchHit>)>::new();
+    for hit in resp.hits {
+        match logs.iter_mut().find(|(uuid, _, _)| *uuid == hit.uuid) {
+            Some((_, _, hits)) => hits.push(hit),
+            None => logs.push((hit.uuid.clone(), hit.note.clone(), vec![hit])),
+        }
+    }
+    Ok(SearchTemplate {
+        query: resp.query,
+        all: query.all,
+        logs,
+        next_offset: resp.next_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_query_into_terms() {
+        assert_eq!(terms("Cargo build --release"), ["cargo", "build", "release"]);
+    }
+
+    #[test]
+    fn snippet_shows_first_matching_line() {
+        let text = "compiling\n  Error: disk FULL  \nerror again";
+        assert_eq!(snippet(text, &terms("full")), "Error: disk FULL");
+        assert_eq!(snippet(text, &terms("nothing")), "compiling");
+    }
+
+    #[test]
+    fn snippet_is_cut_around_the_match() {
+        // `İ` lowercases to two characters, which must not shift the cut.
+        let line = format!("{}needle{}", "İ".repeat(200), "x".repeat(200));
+        let cut = snippet(&line, &terms("NEEDLE"));
+        assert!(cut.starts_with('…') && cut.ends_with('…'));
+        assert_eq!(cut.chars().count(), SNIPPET_CHARS + 2);
+        assert_eq!(cut.find("needle"), Some('…'.len_utf8() + "İ".len() * (SNIPPET_CHARS / 4)));
+    }
+}
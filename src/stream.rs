@@ -0,0 +1,394 @@
+use anyhow::Context;
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Write;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::models::cast::{CastPartial, CastStream};
+use crate::models::log::{ChunkStream, Diagnostic, Event, content_hash, parse_line, strip_timestamps};
+use crate::models::{AppError, Cast, MultipartUpload, ORIGINAL_SUFFIX, Saved, UploadIdentity, UploadResp};
+use crate::upload::{duplicate_resp, idempotency_key, salvage_enabled, save, strict_default, strict_error};
+
+#[derive(Debug, Deserialize)]
+pub struct StreamMeta {
+    #[serde(default)]
+    notes: String,
+    uuid: Option<Uuid>,
+    strict: Option<bool>,
+}
+
+/// The CPU-bound half of a streaming cast, moved onto the work pool for every chunk: the converter
+/// and the compressor archiving the original.
+struct Converting {
+    converter: CastStream,
+    archive: zstd::stream::write::Encoder<'static, Vec<u8>>,
+}
+
+impl Converting {
+    fn new(salvage: bool) -> Self {
+        Self {
+            converter: CastStream::new(salvage),
+            archive: zstd::stream::write::Encoder::new(Vec::new(), 3).expect("zstd level 3 is valid"),
+        }
+    }
+
+    /// Feeds the next bytes of the cast; returns the converted and the compressed output so far.
+    fn push(&mut self, bytes: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        self.converter.push(bytes)?;
+        self.archive.write_all(bytes)?;
+        Ok((self.converter.take_output(), std::mem::take(self.archive.get_mut())))
+    }
+
+    fn finish(self) -> anyhow::Result<(CastPartial, Vec<u8>)> {
+        let partial = self.converter.finish()?;
+        Ok((partial, self.archive.finish()?))
+    }
+}
+
+/// A cast that is converted and written to MinIO while its chunks arrive.
+struct StreamingCast {
+    first_line: usize,
+    chunks: ChunkStream,
+    converting: Option<Converting>,
+    object: MultipartUpload,
+    original: MultipartUpload,
+    checksum: Option<(usize, String)>,
+    /// SHA-256 of the received cast, known once it passed [`Ingest::verify`].
+    digest: String,
+    failed: bool,
+}
+
+struct Ingest<'a> {
+    app: &'a AppState,
+    uuid: Uuid,
+    salvage: bool,
+    stored: HashSet<String>,
+    heartbeats: Vec<OffsetDateTime>,
+    casts: BTreeMap<u128, StreamingCast>,
+    skipped: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Ingest<'_> {
+    /// Reads the whole body and drops the casts that did not arrive intact.
+    async fn run(&mut self, body: Body, strict: bool) -> Result<(), AppError> {
+        let mut stream = body.into_data_stream();
+        let mut pending = Vec::new();
+        let mut lineno = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| AppError::BadRequest(e.into()))?;
+            let mut rest = &chunk[..];
+            while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+                pending.extend_from_slice(&rest[..pos]);
+                lineno += 1;
+                self.line(lineno, &pending).await?;
+                pending.clear();
+                rest = &rest[pos + 1..];
+            }
+            pending.extend_from_slice(rest);
+        }
+        if !pending.is_empty() {
+            self.line(lineno + 1, &pending).await?;
+        }
+
+        self.verify().await;
+        if strict && !self.diagnostics.is_empty() {
+            return Err(strict_error(&self.diagnostics));
+        }
+        Ok(())
+    }
+
+    fn content_hash(&self) -> Option<String> {
+        let filenames = self.casts.keys().map(|f| f.to_string()).collect::<Vec<_>>();
+        let digests = self.casts.values().map(|c| c.digest.as_str());
+        content_hash(&self.heartbeats, filenames.iter().map(String::as_str).zip(digests))
+    }
+
+    async fn line(&mut self, lineno: usize, raw: &[u8]) -> Result<(), AppError> {
+        let Ok(line) = std::str::from_utf8(raw) else {
+            self.diagnostics.push(Diagnostic {
+                line: lineno,
+                kind: None,
+                errors: vec!["line is not valid UTF-8".to_string()],
+            });
+            return Ok(());
+        };
+        let line = strip_timestamps(line);
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let event = match self.app.pool.run(move || parse_line(lineno, &line)).await? {
+            Ok(event) => event,
+            Err(diagnostic) if diagnostic.kind.as_deref() == Some("part") => {
+                return Err(AppError::BadRequest(anyhow::anyhow!(
+                    "chunked pastes are not streamed, send them to /api/upload"
+                )));
+            }
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                return Ok(());
+            }
+        };
+
+        let Event::Cast {
+            filename,
+            content,
+            checksum,
+            offset,
+        } = event
+        else {
+            if let Event::Heartbeat(times) = event {
+                self.heartbeats.extend(times);
+            }
+            return Ok(());
+        };
+        let name = filename.to_string();
+        if self.stored.contains(&name) {
+            self.skipped.insert(name);
+            return Ok(());
+        }
+        let cast = self.casts.entry(filename).or_insert_with(|| StreamingCast {
+            first_line: lineno,
+            chunks: ChunkStream::default(),
+            converting: Some(Converting::new(self.salvage)),
+            object: self.app.minio.stream_cast(&self.uuid, &name),
+            original: self.app.minio.stream_cast(&self.uuid, &format!("{name}{ORIGINAL_SUFFIX}")),
+            checksum: None,
+            digest: String::new(),
+            failed: false,
+        });
+        if cast.failed {
+            return Ok(());
+        }
+        if let Some(checksum) = checksum {
+            cast.checksum.get_or_insert((lineno, checksum));
+        }
+        match cast.chunks.push(offset, content) {
+            Ok(ready) => {
+                let mut converting = cast.converting.take().expect("only taken while converting");
+                let (converting, output) = self
+                    .app
+                    .pool
+                    .run(move || {
+                        let output = converting.push(&ready);
+                        (converting, output)
+                    })
+                    .await?;
+                cast.converting = Some(converting);
+                let (output, archived) = output
+                    .with_context(|| format!("failed to convert cast {name}"))
+                    .map_err(AppError::BadRequest)?;
+                cast.object.write(&output).await.map_err(AppError::StreamStorage)?;
+                cast.original.write(&archived).await.map_err(AppError::StreamStorage)?;
+            }
+            Err(e) => {
+                self.diagnostics.push(Diagnostic {
+                    line: cast.first_line,
+                    kind: Some("cast".to_string()),
+                    errors: vec![format!("cast {filename}: {e}")],
+                });
+                cast.failed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops casts with missing bytes or a checksum mismatch, reporting them like [`parse_log`] does.
+    ///
+    /// [`parse_log`]: crate::models::log::parse_log
+    async fn verify(&mut self) {
+        let mut rejected = Vec::new();
+        for (filename, cast) in self.casts.iter_mut() {
+            if cast.failed {
+                rejected.push(*filename);
+                continue;
+            }
+            let diagnostic = match (std::mem::take(&mut cast.chunks).finish(), &cast.checksum) {
+                (Err(e), _) => Diagnostic {
+                    line: cast.first_line,
+                    kind: Some("cast".to_string()),
+                    errors: vec![format!("cast {filename}: {e}")],
+                },
+                (Ok(actual), Some((line, expected))) if actual != *expected => Diagnostic {
+                    line: *line,
+                    kind: Some("cast".to_string()),
+                    errors: vec![format!(
+                        "cast {filename} is damaged: sha256 {actual} does not match {expected}"
+                    )],
+                },
+                (Ok(actual), _) => {
+                    cast.digest = actual;
+                    continue;
+                }
+            };
+            self.diagnostics.push(diagnostic);
+            rejected.push(*filename);
+        }
+        for filename in rejected {
+            if let Some(cast) = self.casts.remove(&filename) {
+                cast.object.abort().await;
+                cast.original.abort().await;
+            }
+        }
+    }
+
+    /// Converts what is left of every cast and finishes its object.
+    async fn complete(&mut self) -> Result<Vec<Cast>, AppError> {
+        let mut casts = Vec::new();
+        while let Some((filename, cast)) = self.casts.pop_first() {
+            let converting = cast.converting.expect("only taken while converting");
+            let (mut object, mut original) = (cast.object, cast.original);
+            let finished = match self.app.pool.run(move || converting.finish()).await {
+                Ok(Ok(finished)) => Ok(finished),
+                Ok(Err(e)) => Err(AppError::BadRequest(e.context(format!("failed to convert cast {filename}")))),
+                Err(e) => Err(e),
+            };
+            let (partial, archived) = match finished {
+                Ok(finished) => finished,
+                Err(e) => {
+                    object.abort().await;
+                    original.abort().await;
+                    return Err(e);
+                }
+            };
+            let written = async {
+                object.write(partial.content.as_bytes()).await?;
+                original.write(&archived).await
+            };
+            if let Err(e) = written.await {
+                object.abort().await;
+                original.abort().await;
+                return Err(AppError::StreamStorage(e));
+            }
+            original.complete().await.map_err(AppError::StreamStorage)?;
+            let size = object.complete().await.map_err(AppError::StreamStorage)?;
+            let started_at = OffsetDateTime::from_unix_timestamp(partial.timestamp)
+                .context("invalid timestamp")
+                .map_err(AppError::BadRequest)?;
+            casts.push(Cast {
+                filename: filename.to_string(),
+                content: String::new(),
+                original: Some(Vec::new()),
+                size_byte: size as u32,
+                started_at,
+                width: partial.width,
+                height: partial.height,
+                duration: partial.duration,
+                active_duration: partial.active_duration,
+                activity: partial.activity,
+                commands: partial.commands,
+                text: partial.text,
+                event_count: partial.event_count,
+                repaired_events: partial.repaired_events,
+                truncated: partial.truncated,
+                checksum: cast.checksum.map(|(_, checksum)| checksum),
+            });
+        }
+        Ok(casts)
+    }
+
+    async fn abort(&mut self) {
+        while let Some((_, cast)) = self.casts.pop_first() {
+            cast.object.abort().await;
+            cast.original.abort().await;
+        }
+    }
+}
+
+/// Streaming variant of [`upload`]: the body is the log itself, one event per line, with `notes`,
+/// `uuid` and `strict` as query parameters. Lines are parsed as they arrive and each cast is
+/// converted and written to MinIO chunk by chunk, so memory use is bounded by the longest line
+/// rather than the size of the upload. Large casts should therefore be sent as offset chunks, as
+/// the uploader does. Casts are only recorded once the whole body has been read and checked.
+/// Decoding and conversion run on the work pool; a full queue is answered with 503 before the
+/// body is read. A new log repeating an earlier upload is discarded once the body has been checked.
+///
+/// [`upload`]: crate::upload::upload
+pub async fn upload_stream(
+    State(app): State<AppState>,
+    Query(meta): Query<StreamMeta>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let key = idempotency_key(&headers)?;
+    let _ticket = app.pool.admit()?;
+    let uuid = meta.uuid.unwrap_or(Uuid::new_v4());
+    let appended = app.db.query_single_log(&uuid).await?.is_some();
+    let stored = if appended {
+        app.db.query_cast_filenames(&uuid).await?
+    } else {
+        Default::default()
+    };
+
+    let mut ingest = Ingest {
+        app: &app,
+        uuid,
+        salvage: salvage_enabled(),
+        stored,
+        heartbeats: Vec::new(),
+        casts: BTreeMap::new(),
+        skipped: BTreeSet::new(),
+        diagnostics: Vec::new(),
+    };
+    let strict = meta.strict.unwrap_or_else(strict_default);
+    let checked = async {
+        ingest.run(body, strict).await?;
+        let identity = if appended {
+            UploadIdentity::default()
+        } else {
+            UploadIdentity {
+                content_hash: ingest.content_hash(),
+                idempotency_key: key,
+            }
+        };
+        let duplicate = app.db.find_duplicate(&identity).await?;
+        Ok::<_, AppError>((identity, duplicate))
+    }
+    .await;
+    let identity = match checked {
+        Ok((_, Some(existing))) => {
+            ingest.abort().await;
+            return Ok((StatusCode::OK, Json(duplicate_resp(existing, ingest.diagnostics))));
+        }
+        Ok((identity, None)) => identity,
+        Err(e) => {
+            ingest.abort().await;
+            return Err(e);
+        }
+    };
+    let casts = match ingest.complete().await {
+        Ok(casts) => casts,
+        Err(e) => {
+            ingest.abort().await;
+            return Err(e);
+        }
+    };
+    let saved = save(&app, uuid, appended, &meta.notes, &ingest.heartbeats, &casts, &identity).await?;
+    match saved {
+        Saved::Stored(raced) => ingest.skipped.extend(raced),
+        Saved::Duplicate(existing) => {
+            app.minio.delete_log(&uuid).await?;
+            return Ok((StatusCode::OK, Json(duplicate_resp(existing, ingest.diagnostics))));
+        }
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadResp {
+            ok: true,
+            url: format!("/view/{}", uuid),
+            appended,
+            skipped: ingest.skipped.into_iter().collect(),
+            diagnostics: ingest.diagnostics,
+            duplicate: false,
+        }),
+    ))
+}
@@ -1,266 +1,554 @@
 use anyhow::Context;
 use axum::Json;
-use axum::extract::State;
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::extract::{Multipart, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::future::try_join_all;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use serde_json::json;
+use std::collections::HashMap;
 use std::path::Path;
-use time::{Duration, OffsetDateTime};
+use std::sync::atomic::{AtomicU32, Ordering};
+use time::OffsetDateTime;
 use tokio::try_join;
 use uuid::Uuid;
-use binrw::BinRead;
 
 use crate::AppState;
-use crate::models::log::{CastRaw, parse_log};
-use crate::models::{AppError, Cast, Heartbeats, UploadResp};
+use crate::jobs::enqueue;
+use crate::models::cast::{Truncation, convert_cast, convert_typescript};
+use crate::models::import::{Format, detect};
+use crate::models::log::{
+    CastRaw, Diagnostic, ParsedLog, PartHeader, cast_checksum, content_hash, parse_cast_filename, parse_heartbeats, parse_log,
+    split_part,
+};
+use crate::models::{AppError, Cast, HEARTBEAT_GAP, env_or, Heartbeats, Saved, UploadIdentity, UploadResp, WorkPool};
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Header {
-    version: u8,
-    width: u16,
-    height: u16,
-    timestamp: i64,
-    env: Value,
+/// Whether casts cut off mid-event keep the events decoded before the damage.
+pub(crate) fn salvage_enabled() -> bool {
+    env_or("CAST_SALVAGE", true)
 }
 
-#[derive(Debug)]
-enum Event {
-    Input { elapsed: f32, data: String },
-    Output { elapsed: f32, data: String },
-    Resize { elapsed: f32, cols: u16, rows: u16 },
+/// Folds raw heartbeats into intervals, starting a new one after a gap of more than [`HEARTBEAT_GAP`].
+fn heartbeat_intervals(hbs_raw: &[OffsetDateTime]) -> Heartbeats {
+    let gap = HEARTBEAT_GAP;
+    hbs_raw
+        .iter()
+        .copied()
+        .fold(Vec::<(OffsetDateTime, OffsetDateTime)>::new(), |mut acc, x| {
+            match acc.last_mut() {
+                Some((_, end)) if x - *end <= gap => *end = x,
+                _ => acc.push((x, x)),
+            }
+            acc
+        })
 }
 
-impl Event {
-    fn to_json(&self) -> anyhow::Result<String> {
-        match self {
-            Event::Input { elapsed, data } => {
-                serde_json::to_string(&json!([elapsed, "i", data])).context("failed to serialize input event")
-            }
-            Event::Output { elapsed, data } => {
-                serde_json::to_string(&json!([elapsed, "o", data])).context("failed to serialize output event")
-            }
-            Event::Resize { elapsed, cols, rows } => {
-                serde_json::to_string(&json!([elapsed, "r", format!("{}x{}", cols, rows)]))
-                    .context("failed to serialize resize event")
-            }
-        }
-    }
-    fn get_elapsed(&self) -> f32 {
-        match self {
-            Event::Input { elapsed, .. } => *elapsed,
-            Event::Output { elapsed, .. } => *elapsed,
-            Event::Resize { elapsed, .. } => *elapsed,
-        }
-    }
-    fn set_elapsed(&mut self, new: f32) {
-        match self {
-            Event::Input { elapsed, .. } | Event::Output { elapsed, .. } | Event::Resize { elapsed, .. } => {
-                *elapsed = new
-            }
-        }
-    }
+fn convert(cast: CastRaw, salvage: bool) -> anyhow::Result<Cast> {
+    let filename = Path::new(&cast.filename)
+        .file_name()
+        .context("invalid filename")?
+        .to_string_lossy()
+        .to_string();
+    let original = cast
+        .archive
+        .then(|| zstd::encode_all(&cast.content[..], 3).context("failed to compress original"))
+        .transpose()?;
+    let cast_partial =
+        convert_cast(cast.content, salvage).with_context(|| format!("failed to convert cast {filename}"))?;
+    let datetime = OffsetDateTime::from_unix_timestamp(cast_partial.timestamp).context("invalid timestamp")?;
+    Ok(Cast {
+        filename,
+        started_at: datetime,
+        size_byte: cast_partial.content.len() as u32,
+        content: cast_partial.content,
+        original,
+        width: cast_partial.width,
+        height: cast_partial.height,
+        duration: cast_partial.duration,
+        active_duration: cast_partial.active_duration,
+        activity: cast_partial.activity,
+        commands: cast_partial.commands,
+        text: cast_partial.text,
+        event_count: cast_partial.event_count,
+        repaired_events: cast_partial.repaired_events,
+        truncated: cast_partial.truncated,
+        checksum: cast.checksum,
+    })
 }
 
-use unsigned_varint::io::read_u32;
-impl BinRead for Event {
-    type Args<'a> = ();
-    fn read_options<R: std::io::Read + binrw::io::Seek>(
-        reader: &mut R,
-        _endian: binrw::Endian,
-        _args: Self::Args<'_>,
-    ) -> binrw::BinResult<Self> {
-        let elapsed = f32::read_le(reader)?;
-        let kind = u8::read_le(reader)?;
-
-        Ok(match kind {
-            0 | 1 => {
-                let len = read_u32(&mut *reader).map_err(|e| binrw::Error::Io(e.into()))? as usize;
-                let mut buf = vec![0; len];
-                reader.read_exact(&mut buf)?;
-                let data = String::from_utf8(buf).map_err(|e| binrw::Error::AssertFail {
-                    pos: reader.stream_position().unwrap(),
-                    message: format!("utf-8 error: {e:?}"),
-                })?;
-                if kind == 0 {
-                    Event::Input { elapsed, data }
-                } else {
-                    Event::Output { elapsed, data }
-                }
-            }
-            2 => {
-                let rows = u16::read_le(reader)?;
-                let cols = u16::read_le(reader)?;
-                Event::Resize { elapsed, cols, rows }
-            }
-            _ => {
-                return Err(binrw::Error::AssertFail {
-                    pos: reader.stream_position()?,
-                    message: format!("unknown kind {kind}"),
-                });
-            }
-        })
+/// How many of an upload's casts have been converted so far.
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    pub done: AtomicU32,
+    pub total: AtomicU32,
+}
+
+impl Progress {
+    pub fn get(&self) -> (u32, u32) {
+        (self.done.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
     }
 }
 
-struct CastPartial {
-    timestamp: i64,
-    duration: Duration,
-    active_duration: Duration,
-    event_count: u32,
-    content: String,
+/// Converts every cast on the work pool, in parallel as far as free workers allow.
+async fn process(pool: &WorkPool, casts_raw: Vec<CastRaw>, progress: &Progress) -> Result<Vec<Cast>, AppError> {
+    let salvage = salvage_enabled();
+    progress.total.store(casts_raw.len() as u32, Ordering::Relaxed);
+    let jobs = casts_raw.into_iter().map(|cast| async move {
+        let cast = pool.run(move || convert(cast, salvage)).await?;
+        progress.done.fetch_add(1, Ordering::Relaxed);
+        Ok::<_, AppError>(cast)
+    });
+    try_join_all(jobs)
+        .await?
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(AppError::BadRequest)
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UploadMeta {
+    notes: String,
+    logs: String,
+    uuid: Option<Uuid>,
+    strict: Option<bool>,
 }
 
-fn convert_cast(src: Vec<u8>) -> anyhow::Result<CastPartial> {
-    #[derive(Debug, BinRead)]
-    #[brw(little)]
-    struct CastHeader {
-        ts: u128,
+/// Whether lines that fail to parse reject the whole upload, unless the request says otherwise.
+pub(crate) fn strict_default() -> bool {
+    env_or("UPLOAD_STRICT", false)
+}
+
+/// The error a strict upload fails with when some of its lines did not parse.
+pub(crate) fn strict_error(diagnostics: &[Diagnostic]) -> AppError {
+    let lines = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("\n");
+    AppError::BadRequest(anyhow::anyhow!("{} line(s) failed to parse:\n{lines}", diagnostics.len()))
+}
+
+/// The client's `Idempotency-Key` header: repeating a request with the same key returns the
+/// outcome of the first one instead of storing the upload again.
+pub(crate) fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= 255 && key.bytes().all(|b| b.is_ascii_graphic()) => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(AppError::BadRequest(anyhow::anyhow!(
+            "Idempotency-Key must be 1 to 255 visible ASCII characters"
+        ))),
     }
-    let length = src.len();
-    let mut cur = binrw::io::Cursor::new(src);
-    let cast_header: CastHeader = CastHeader::read_le(&mut cur)?;
-    let mut events = Vec::new();
-    while (cur.position() as usize) < length {
-        let event = Event::read_le(&mut cur)?;
-        events.push(event);
+}
+
+/// Answer to an upload that repeats the one stored as `existing`.
+pub(crate) fn duplicate_resp(existing: Uuid, diagnostics: Vec<Diagnostic>) -> UploadResp {
+    UploadResp {
+        ok: true,
+        url: format!("/view/{}", existing),
+        appended: false,
+        skipped: Vec::new(),
+        diagnostics,
+        duplicate: true,
     }
+}
 
-    let event_count = events.len();
+/// Converts and stores an upload. When `uuid` names an existing log the upload is appended to it:
+/// casts whose filename is already stored are skipped and heartbeats are merged into the existing intervals.
+/// A new log whose content or idempotency key matches an earlier upload is not stored again; the
+/// response points at the earlier log instead.
+pub(crate) async fn store(
+    app: &AppState,
+    uuid: Uuid,
+    notes: &String,
+    parsed: ParsedLog,
+    idempotency_key: Option<String>,
+    progress: &Progress,
+) -> Result<UploadResp, AppError> {
+    let ParsedLog {
+        heartbeats: hbs_raw,
+        casts: casts_raw,
+        diagnostics,
+    } = parsed;
+    let appended = app.db.query_single_log(&uuid).await?.is_some();
+    let stored = if appended {
+        app.db.query_cast_filenames(&uuid).await?
+    } else {
+        Default::default()
+    };
+    let identity = if appended {
+        UploadIdentity::default()
+    } else {
+        let checksums = casts_raw
+            .iter()
+            .map(|c| (c.filename.as_str(), c.checksum.clone().unwrap_or_else(|| cast_checksum(&c.content))))
+            .collect::<Vec<_>>();
+        UploadIdentity {
+            content_hash: content_hash(&hbs_raw, checksums.iter().map(|(f, c)| (*f, c.as_str()))),
+            idempotency_key,
+        }
+    };
+    if let Some(existing) = app.db.find_duplicate(&identity).await? {
+        return Ok(duplicate_resp(existing, diagnostics));
+    }
+    let (casts_raw, skipped): (Vec<_>, Vec<_>) = casts_raw.into_iter().partition(|c| !stored.contains(&c.filename));
+    let mut skipped: Vec<String> = skipped.into_iter().map(|c| c.filename).collect();
 
-    let timestamp = cast_header.ts;
+    let casts = process(&app.pool, casts_raw, progress).await?;
 
-    let duration = events
-        .iter()
-        .rev()
-        .find_map(|e| match e {
-            Event::Output { elapsed, .. } => Some(Duration::seconds_f32(*elapsed)),
-            _ => None,
-        })
-        .unwrap_or(Duration::ZERO);
-
-    let duration_active = events
-        .windows(2)
-        .rev()
-        .find_map(|w| match (&w[0], &w[1]) {
-            (Event::Output { .. }, Event::Output { elapsed, .. }) => Some(Duration::seconds_f32(*elapsed)),
-            _ => None,
-        })
-        .unwrap_or(Duration::ZERO);
-
-    let header = json!({
-        "version": 3,
-        "term": {
-            "cols": 80,
-            "rows": 24,
-            "type": "xterm-color"
-        },
-        "timestamp": timestamp,
-        "env": {
-            "SHELL": "/bin/bash",
-            "TERM": "xterm-color",
+    let (_, saved) = try_join!(
+        async {
+            app.minio.upload_casts(&uuid, &casts).await?;
+            Ok::<_, AppError>(())
         },
-    });
-    let header = serde_json::to_string(&header).context("failed to serialize header")?;
-
-    let mut prev = 0.0;
-    for ev in events.iter_mut() {
-        let cur = ev.get_elapsed();
-        ev.set_elapsed(cur - prev);
-        prev = cur;
+        save(app, uuid, appended, notes, &hbs_raw, &casts, &identity),
+    )?;
+    match saved {
+        Saved::Stored(raced) => skipped.extend(raced),
+        Saved::Duplicate(existing) => {
+            app.minio.delete_log(&uuid).await?;
+            return Ok(duplicate_resp(existing, diagnostics));
+        }
     }
-    let body = events
-        .iter()
-        .filter_map(|e| e.to_json().ok())
-        .collect::<Vec<String>>()
-        .join("\n");
-    let content = format!("{header}\n{body}\n");
-
-    Ok(CastPartial {
-        timestamp: (timestamp / 1000) as i64,
-        duration,
-        active_duration: duration_active,
-        event_count: event_count as u32,
-        content,
+
+    Ok(UploadResp {
+        ok: true,
+        url: format!("/view/{}", uuid),
+        appended,
+        skipped,
+        diagnostics,
+        duplicate: false,
     })
 }
 
-fn process(hbs_raw: &[OffsetDateTime], casts_raw: &[CastRaw]) -> anyhow::Result<(Heartbeats, Vec<Cast>)> {
-    let gap = Duration::seconds(10);
-    let itvs = hbs_raw
+/// Stores the heartbeats and records the log and its casts, whose content is already in MinIO or
+/// on its way there. Heartbeats appended to an existing log get a file of their own.
+pub(crate) async fn save(
+    app: &AppState,
+    uuid: Uuid,
+    appended: bool,
+    notes: &String,
+    hbs_raw: &[OffsetDateTime],
+    casts: &[Cast],
+    identity: &UploadIdentity,
+) -> Result<Saved, AppError> {
+    let hb_itvs = heartbeat_intervals(hbs_raw);
+    let hbs_name = if appended {
+        format!("heartbeats-{}.log", OffsetDateTime::now_utc().unix_timestamp())
+    } else {
+        "heartbeats.log".to_string()
+    };
+    let hbs_raw = format!("{:?}", hbs_raw);
+
+    let (_, saved) = try_join!(
+        async {
+            app.minio.upload_heartbeats(&uuid, &hbs_name, &hbs_raw).await?;
+            Ok::<_, AppError>(())
+        },
+        async { Ok::<_, AppError>(app.db.insert(&uuid, notes, &hb_itvs, casts, identity).await?) },
+    )?;
+    Ok(saved)
+}
+
+#[derive(Serialize)]
+struct PartResp {
+    ok: bool,
+    upload_id: String,
+    received: Vec<u32>,
+    total: u32,
+}
+
+/// Outcome of staging one part of a chunked upload.
+enum Staged {
+    Pending(PartResp),
+    Complete { notes: String, logs: String },
+}
+
+async fn stage(app: &AppState, part: &PartHeader, notes: &str, body: &str) -> Result<Staged, AppError> {
+    let parts = app
+        .db
+        .stage_part(&part.upload_id, part.part, part.total, notes, body)
+        .await?;
+    if let Some(other) = parts.iter().find(|p| p.total != part.total) {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "upload {} was announced with {} parts but part {} says {}",
+            part.upload_id,
+            other.total,
+            part.part,
+            part.total
+        )));
+    }
+    if parts.len() < part.total as usize {
+        return Ok(Staged::Pending(PartResp {
+            ok: true,
+            upload_id: part.upload_id.clone(),
+            received: parts.iter().map(|p| p.part).collect(),
+            total: part.total,
+        }));
+    }
+
+    let notes = parts
         .iter()
-        .copied()
-        .fold(Vec::<(OffsetDateTime, OffsetDateTime)>::new(), |mut acc, x| {
-            match acc.last_mut() {
-                Some((_, end)) if x - *end <= gap => *end = x,
-                _ => acc.push((x, x)),
+        .map(|p| p.notes.trim())
+        .filter(|n| !n.is_empty())
+        .fold(Vec::<&str>::new(), |mut acc, n| {
+            if !acc.contains(&n) {
+                acc.push(n);
             }
             acc
-        });
-
-    let casts = casts_raw
-        .iter()
-        .map(|cast| {
-            let filename = Path::new(&cast.filename)
-                .file_name()
-                .context("invalid filename")?
-                .to_string_lossy()
-                .to_string();
-            let content = cast.content.clone();
-            let cast_partial = convert_cast(content)?;
-            let datetime = OffsetDateTime::from_unix_timestamp(cast_partial.timestamp).context("invalid timestamp")?;
-            anyhow::Ok(Cast {
-                filename,
-                started_at: datetime,
-                content: cast_partial.content,
-                duration: cast_partial.duration,
-                active_duration: cast_partial.active_duration,
-                event_count: cast_partial.event_count,
-            })
         })
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    Ok((itvs, casts))
+        .join("\n");
+    let logs = parts.into_iter().map(|p| p.payload).collect::<Vec<_>>().join("\n");
+    Ok(Staged::Complete { notes, logs })
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct UploadMeta {
-    notes: String,
-    logs: String,
-    uuid: Option<Uuid>,
+#[derive(Serialize)]
+struct JobResp {
+    ok: bool,
+    job_id: Uuid,
+    uuid: Uuid,
+    status_url: String,
 }
 
+/// Queues a pasted log for the background workers and answers `202 Accepted` with the job to poll
+/// at `/api/jobs/{id}`. A paste starting with a `part` line is one part of a chunked upload: it is
+/// staged and answered with the parts received so far until every part has arrived, then the
+/// reassembled payload is queued as a whole. Repeating a request with the same `Idempotency-Key`
+/// returns the job queued the first time.
 pub async fn upload(
     State(app): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<UploadMeta>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    let key = idempotency_key(&headers)?;
     let uuid = payload.uuid.unwrap_or(Uuid::new_v4());
-    let notes = payload.notes;
-    let (hbs_raw, casts_raw) = parse_log(&payload.logs);
+    let mut notes = payload.notes;
+    let mut logs = payload.logs;
 
-    let (hb_itvs, casts) = process(&hbs_raw, &casts_raw).map_err(AppError::BadRequest)?;
-    let hbs_raw = format!("{:?}", hbs_raw);
+    let part = split_part(&logs).map_err(AppError::BadRequest)?;
+    if let Some((part, body)) = &part {
+        match stage(&app, part, &notes, body).await? {
+            Staged::Pending(resp) => return Ok((StatusCode::ACCEPTED, Json(resp)).into_response()),
+            Staged::Complete {
+                notes: all_notes,
+                logs: all_logs,
+            } => {
+                notes = all_notes;
+                logs = all_logs;
+            }
+        }
+    }
+    let strict = payload.strict.unwrap_or_else(strict_default);
+    let (job_id, uuid) = enqueue(&app, uuid, &notes, logs, strict, key).await?;
+    if let Some((part, _)) = &part {
+        app.db.delete_parts(&part.upload_id).await?;
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(JobResp {
+            ok: true,
+            job_id,
+            uuid,
+            status_url: format!("/api/jobs/{job_id}"),
+        }),
+    )
+        .into_response())
+}
 
-    try_join!(
-        async {
-            app.minio.upload_casts(&uuid, &casts).await?;
-            Ok::<_, AppError>(())
-        },
-        async {
-            app.minio.upload_heartbeats(&uuid, &hbs_raw).await?;
-            Ok::<_, AppError>(())
-        },
-        async {
-            app.db.insert(&uuid, &notes, &hb_itvs, &casts).await?;
-            Ok::<_, AppError>(())
-        },
-    )?;
+#[derive(Serialize)]
+struct PreviewHeartbeat {
+    #[serde(with = "time::serde::rfc3339")]
+    started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    ended_at: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+struct PreviewCast {
+    filename: String,
+    #[serde(with = "time::serde::rfc3339")]
+    started_at: OffsetDateTime,
+    width: u16,
+    height: u16,
+    duration_ms: u64,
+    active_duration_ms: u64,
+    event_count: u32,
+    repaired_events: u32,
+    truncated: Option<Truncation>,
+    sha256: Option<String>,
+    size_byte: u32,
+}
+
+#[derive(Serialize)]
+struct PreviewResp {
+    ok: bool,
+    heartbeats: Vec<PreviewHeartbeat>,
+    casts: Vec<PreviewCast>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Dry run of [`upload`]: parses and converts the payload and reports what would be stored,
+/// without touching MinIO or the database.
+pub async fn preview(State(app): State<AppState>, Json(payload): Json<UploadMeta>) -> Result<impl IntoResponse, AppError> {
+    let _ticket = app.pool.admit()?;
+    let parsed = app.pool.run(move || parse_log(&payload.logs)).await?;
+    let hb_itvs = heartbeat_intervals(&parsed.heartbeats);
+    let casts = process(&app.pool, parsed.casts, &Progress::default()).await?;
+
+    let heartbeats = hb_itvs
+        .into_iter()
+        .map(|(started_at, ended_at)| PreviewHeartbeat { started_at, ended_at })
+        .collect();
+    let casts = casts
+        .into_iter()
+        .map(|cast| PreviewCast {
+            size_byte: cast.size_byte,
+            filename: cast.filename,
+            started_at: cast.started_at,
+            width: cast.width,
+            height: cast.height,
+            duration_ms: cast.duration.whole_milliseconds() as u64,
+            active_duration_ms: cast.active_duration.whole_milliseconds() as u64,
+            event_count: cast.event_count,
+            repaired_events: cast.repaired_events,
+            truncated: cast.truncated,
+            sha256: cast.checksum,
+        })
+        .collect();
 
     Ok((
-        StatusCode::CREATED,
-        Json(UploadResp {
+        StatusCode::OK,
+        Json(PreviewResp {
             ok: true,
-            url: format!("/view/{}", uuid),
+            heartbeats,
+            casts,
+            diagnostics: parsed.diagnostics,
         }),
     ))
 }
+
+/// Storage name for an imported cast: the file stem, restricted to characters that are safe in an object key.
+fn import_filename(file_name: &str) -> String {
+    let stem = Path::new(file_name).file_stem().unwrap_or_default().to_string_lossy();
+    stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+/// The typescript a `script -t` timing file belongs to: `session.timing` goes with `session` or
+/// `session.typescript`, and a bare `timing` with `script`'s default `typescript`.
+fn timing_key(file_name: &str) -> String {
+    match import_filename(file_name) {
+        stem if stem == "timing" => "typescript".to_string(),
+        stem => stem,
+    }
+}
+
+/// Multipart variant of [`upload`] taking the workspace-logs files as they are on disk: the
+/// `heartbeat.log` part is the raw heartbeat file and every other file is a recording. Recordings
+/// may be binary casts, asciicast v2/v3 or ttyrec files, told apart by content, or `script -t`
+/// typescripts sent together with their `.timing` file. Text fields `notes` and `uuid` mirror
+/// [`UploadMeta`].
+pub async fn upload_raw(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let key = idempotency_key(&headers)?;
+    let _ticket = app.pool.admit()?;
+    let mut notes = String::new();
+    let mut uuid = None;
+    let mut hbs_raw = Vec::new();
+    let mut recordings = Vec::new();
+    let mut timings = HashMap::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::BadRequest(e.into()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(str::to_string);
+        let data = field.bytes().await.map_err(|e| AppError::BadRequest(e.into()))?;
+
+        match (name.as_str(), file_name) {
+            ("notes", None) => notes = String::from_utf8_lossy(&data).into_owned(),
+            ("uuid", None) => {
+                let text = String::from_utf8_lossy(&data);
+                uuid = Some(Uuid::parse_str(text.trim()).map_err(|e| AppError::BadRequest(e.into()))?);
+            }
+            (_, Some(file_name)) if file_name.ends_with("heartbeat.log") => {
+                hbs_raw.extend(parse_heartbeats(&data).map_err(AppError::BadRequest)?);
+            }
+            (_, Some(file_name)) if file_name == "timing" || file_name.ends_with(".timing") => {
+                timings.insert(timing_key(&file_name), data);
+            }
+            (_, Some(file_name)) => recordings.push((file_name, data)),
+            (_, None) => {
+                return Err(AppError::BadRequest(anyhow::anyhow!("unexpected multipart field {name:?}")));
+            }
+        }
+    }
+
+    let mut casts_raw = Vec::new();
+    // Stored name of each recording and the file it came from, so two files never share a key.
+    let mut names = HashMap::<String, String>::new();
+    let mut claim = |filename: &str, file_name: &str| match names.insert(filename.to_string(), file_name.to_string()) {
+        Some(other) => Err(AppError::BadRequest(anyhow::anyhow!(
+            "{other:?} and {file_name:?} would both be stored as {filename:?}; rename one of them"
+        ))),
+        None => Ok(()),
+    };
+    for (file_name, data) in recordings {
+        let checksum = Some(cast_checksum(&data));
+        if let Some(timing) = timings.remove(&import_filename(&file_name)) {
+            claim(&import_filename(&file_name), &file_name)?;
+            let typescript = data.clone();
+            let cast = app
+                .pool
+                .run(move || convert_typescript(&typescript, &timing))
+                .await?
+                .with_context(|| format!("failed to import typescript {file_name}"))
+                .map_err(AppError::BadRequest)?;
+            casts_raw.push(CastRaw {
+                filename: import_filename(&file_name),
+                checksum,
+                content: cast.content.into_bytes(),
+                archive: false,
+            });
+            continue;
+        }
+        // Recorder casts are named by their start time; imported recordings keep their own name.
+        let filename = match (detect(&data), parse_cast_filename(&file_name)) {
+            (Format::Binary, Ok(ts)) if file_name.ends_with(".cast") => ts.to_string(),
+            (Format::Binary, _) => {
+                return Err(AppError::BadRequest(anyhow::anyhow!(
+                    "{file_name:?} is neither a timestamp-named .cast nor a recognised recording"
+                )));
+            }
+            _ => import_filename(&file_name),
+        };
+        claim(&filename, &file_name)?;
+        casts_raw.push(CastRaw {
+            filename,
+            checksum,
+            content: data.to_vec(),
+            archive: true,
+        });
+    }
+    if let Some(key) = timings.keys().next() {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "timing file for {key:?} has no matching typescript"
+        )));
+    }
+
+    casts_raw.sort_by_key(|c| c.filename.parse::<u128>().unwrap_or_default());
+
+    let uuid = uuid.unwrap_or(Uuid::new_v4());
+    let parsed = ParsedLog {
+        heartbeats: hbs_raw,
+        casts: casts_raw,
+        diagnostics: Vec::new(),
+    };
+    let resp = store(&app, uuid, &notes, parsed, key, &Progress::default()).await?;
+    let status = if resp.duplicate { StatusCode::OK } else { StatusCode::CREATED };
+    Ok((status, Json(resp)))
+}
@@ -1,5 +1,5 @@
 use crate::AppState;
-use crate::models::{AppError, MarkMeta, filters};
+use crate::models::{AppError, CommandMeta, MarkMeta, filters};
 use askama::Template;
 use askama_web::WebTemplate;
 use axum::extract::{Path, State};
@@ -15,11 +15,18 @@ struct Cast {
     bucket: String,
     path: String,
     size_byte: u32,
+    width: u16,
+    height: u16,
     duration: Duration,
     active_duration: Duration,
+    activity: Vec<u32>,
     event_count: u32,
+    repaired_events: u32,
+    truncated_at: Option<u64>,
+    truncated_reason: Option<String>,
     started_at: OffsetDateTime,
     marks: Vec<MarkMeta>,
+    commands: Vec<CommandMeta>,
 }
 
 #[derive(Template, WebTemplate)]
@@ -40,6 +47,13 @@ impl Cast {
         let s = self.duration.whole_seconds();
         format!("{}m{:02}s", s / 60, s % 60)
     }
+    pub fn failed_commands(&self) -> usize {
+        self.commands.iter().filter(|c| c.failed()).count()
+    }
+    pub fn active_mmss(&self) -> String {
+        let s = self.active_duration.whole_seconds();
+        format!("{}m{:02}s", s / 60, s % 60)
+    }
 }
 
 pub async fn view(State(app): State<AppState>, Path(id): Path<Uuid>) -> Result<ViewTemplate, AppError> {
@@ -53,17 +67,24 @@ pub async fn view(State(app): State<AppState>, Path(id): Path<Uuid>) -> Result<V
     let casts: Vec<Cast> = futures::future::try_join_all(casts.into_iter().map(|cast| {
         let db = app.db.clone();
         async move {
-            let marks = db.query_marks(cast.id).await?;
+            let (marks, commands) = try_join!(db.query_marks(cast.id), db.query_commands(cast.id))?;
             anyhow::Ok(Cast {
                 id: cast.id,
                 bucket: cast.bucket.clone(),
                 path: cast.path.clone(),
                 size_byte: cast.size_byte,
+                width: cast.width,
+                height: cast.height,
                 duration: cast.duration,
                 active_duration: cast.active_duration,
+                activity: cast.activity,
                 event_count: cast.event_count,
+                repaired_events: cast.repaired_events,
+                truncated_at: cast.truncated_at,
+                truncated_reason: cast.truncated_reason.clone(),
                 started_at: cast.started_at,
                 marks,
+                commands,
             })
         }
     }))
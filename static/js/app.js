@@ -150,6 +150,9 @@ export class PtyPlayer {
     getCurrentTime() {
         return this.#core.getCurrentTime();
     }
+    seek(t) {
+        return this.#core.seek(t);
+    }
     dispose() {
         this.#core.dispose();
     }